@@ -10,7 +10,7 @@ use tantivy::schema::Schema;
 
 use toshi_types::*;
 
-use crate::Result;
+use crate::{multi_search_body, Result};
 
 #[derive(Debug, Clone)]
 pub struct HyperToshi<C>
@@ -143,4 +143,15 @@ where
         let request = Request::get(uri).body(Body::empty())?;
         self.make_request::<SearchResults<D>>(request).await
     }
+
+    async fn multi_search<I, D>(&self, searches: Vec<(I, Search)>) -> Result<Vec<MultiSearchResult<D>>>
+    where
+        I: ToString + Send + Sync + Display,
+        D: DeserializeOwned + Clone + Send + Sync,
+    {
+        let uri = self.uri("_msearch");
+        let body = multi_search_body(searches)?;
+        let request = Request::post(uri).body(Body::from(body))?;
+        self.make_request::<Vec<MultiSearchResult<D>>>(request).await
+    }
 }
@@ -7,7 +7,7 @@ use tantivy::schema::Schema;
 use async_trait::async_trait;
 use toshi_types::*;
 
-use crate::{AsyncClient, Result, SyncClient};
+use crate::{multi_search_body, AsyncClient, Result, SyncClient};
 use isahc::{HttpClient, Response};
 use std::fmt::Display;
 
@@ -97,6 +97,16 @@ impl AsyncClient for ToshiClient {
         let uri = self.uri(index);
         self.client.get_async(uri).await?.json().await.map_err(Into::into)
     }
+
+    async fn multi_search<I, D>(&self, searches: Vec<(I, Search)>) -> Result<Vec<MultiSearchResult<D>>>
+    where
+        I: ToString + Send + Sync + Display,
+        D: DeserializeOwned + Clone + Send + Sync + Unpin,
+    {
+        let uri = self.uri("_msearch");
+        let body = multi_search_body(searches)?;
+        self.client.post_async(uri, body).await?.json().await.map_err(Into::into)
+    }
 }
 
 impl SyncClient for ToshiClient {
@@ -24,6 +24,18 @@ mod hyper_client;
 
 pub type Result<T> = std::result::Result<T, ToshiClientError>;
 
+/// Build the newline delimited header/body pairs a _msearch request expects
+pub(crate) fn multi_search_body<I: ToString>(searches: Vec<(I, Search)>) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for (index, search) in searches {
+        serde_json::to_writer(&mut body, &MultiSearchHeader { index: index.to_string() })?;
+        body.push(b'\n');
+        serde_json::to_writer(&mut body, &search)?;
+        body.push(b'\n');
+    }
+    Ok(body)
+}
+
 #[async_trait]
 pub trait AsyncClient {
     type Body;
@@ -54,6 +66,11 @@ pub trait AsyncClient {
     where
         I: ToString + Send + Sync + Display,
         D: DeserializeOwned + Clone + Send + Sync + Unpin;
+
+    async fn multi_search<I, D>(&self, searches: Vec<(I, Search)>) -> Result<Vec<MultiSearchResult<D>>>
+    where
+        I: ToString + Send + Sync + Display,
+        D: DeserializeOwned + Clone + Send + Sync + Unpin;
 }
 
 pub trait SyncClient {
@@ -0,0 +1,109 @@
+//! Throughput and peak memory of bulk inserts for a few parsing thread counts and buffer sizes.
+//!
+//! Run with `cargo bench -p toshi-server --bench bulk_ingest`, `BULK_BENCH_DOCS` sets how many documents are
+//! inserted in each run. Peak memory is read from `/proc`, so it's only reported on Linux.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::stream;
+use hyper::{Body, StatusCode};
+use tantivy::schema::{SchemaBuilder, STORED, TEXT};
+use tantivy::Index;
+
+use toshi_server::handlers::{bulk_insert, ParserPool};
+use toshi_server::index::IndexCatalog;
+use toshi_server::tasks::TaskRegistry;
+
+const DEFAULT_DOCS: usize = 200_000;
+const CHUNK_SIZE: usize = 64 * 1024;
+const MAX_LINE_LENGTH: usize = 10_000;
+
+fn create_catalog() -> Arc<IndexCatalog> {
+    let mut builder = SchemaBuilder::new();
+    builder.add_text_field("title", TEXT | STORED);
+    builder.add_text_field("body", TEXT);
+    builder.add_i64_field("rank", STORED);
+    builder.add_u64_field("views", STORED);
+    let index = Index::create_in_ram(builder.build());
+    Arc::new(IndexCatalog::from_index("bench".into(), index).expect("Could not create the bench catalog"))
+}
+
+fn create_body(docs: usize) -> Vec<u8> {
+    let mut body = Vec::new();
+    for i in 0..docs {
+        let line = format!(
+            r#"{{"title": "Document {}", "body": "The quick brown fox number {} jumps over the lazy dog {} times", "rank": {}, "views": {}}}"#,
+            i,
+            i,
+            i % 97,
+            i as i64 - 1000,
+            i * 7
+        );
+        body.extend_from_slice(line.as_bytes());
+        body.push(b'\n');
+    }
+    body
+}
+
+/// The peak resident set size of this process in kB since it was last reset
+fn peak_rss_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Reset the peak resident set size to the current one, so each run reports its own peak
+fn reset_peak_rss() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+async fn run(body: &Arc<Vec<u8>>, docs: usize, threads: usize, buffer: usize) {
+    let catalog = create_catalog();
+    let chunks: Vec<Result<Vec<u8>, std::io::Error>> = body.chunks(CHUNK_SIZE).map(|c| Ok(c.to_vec())).collect();
+    reset_peak_rss();
+
+    let start = Instant::now();
+    let resp = bulk_insert(
+        Arc::clone(&catalog),
+        Arc::new(TaskRegistry::default()),
+        Body::wrap_stream(stream::iter(chunks)),
+        "bench",
+        "",
+        Arc::new(ParserPool::new(threads)),
+        buffer,
+        MAX_LINE_LENGTH,
+    )
+    .await
+    .expect("Bulk insert failed");
+    let elapsed = start.elapsed();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    let secs = elapsed.as_secs_f64();
+    let peak = peak_rss_kb().map(|kb| format!("{} MB", kb / 1024)).unwrap_or_else(|| "n/a".into());
+    println!(
+        "threads={:<3} buffer={:<7} {:>8.0} docs/s {:>7.1} MB/s  took {:>6.2}s  peak rss {}",
+        threads,
+        buffer,
+        docs as f64 / secs,
+        body.len() as f64 / secs / (1024.0 * 1024.0),
+        secs,
+        peak
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let docs = std::env::var("BULK_BENCH_DOCS")
+        .ok()
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(DEFAULT_DOCS);
+    let body = Arc::new(create_body(docs));
+    println!("Inserting {} documents ({} MB) per run", docs, body.len() / (1024 * 1024));
+
+    for threads in [1, 2, 4] {
+        for buffer in [100, 10_000] {
+            run(&body, docs, threads, buffer).await;
+        }
+    }
+}
@@ -2,7 +2,7 @@ use std::error::Error;
 use std::fs::create_dir;
 use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
-use std::sync::{atomic::AtomicBool, Arc};
+use std::sync::Arc;
 
 use futures::prelude::*;
 
@@ -73,8 +73,7 @@ async fn setup_catalog(settings: &Settings) -> Result<SharedCatalog, toshi_types
 }
 
 fn run_master(catalog: SharedCatalog, settings: Settings) -> impl Future<Output = Result<(), hyper::Error>> + Unpin + Send {
-    let bulk_lock = Arc::new(AtomicBool::new(false));
-    let commit_watcher = watcher(Arc::clone(&catalog), settings.auto_commit_duration, Arc::clone(&bulk_lock));
+    let commit_watcher = watcher(Arc::clone(&catalog), settings.auto_commit_duration);
     let addr: IpAddr = settings
         .host
         .parse()
@@ -84,7 +83,6 @@ fn run_master(catalog: SharedCatalog, settings: Settings) -> impl Future<Output
     println!("{}", HEADER);
 
     tokio::spawn(commit_watcher);
-    let watcher_clone = Arc::clone(&bulk_lock);
-    let router = Router::from_settings(catalog, watcher_clone, settings);
+    let router = Router::from_settings(catalog, settings);
     Box::pin(router.router_with_catalog(bind))
 }
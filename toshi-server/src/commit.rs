@@ -1,30 +1,37 @@
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use log::trace;
+use log::{error, trace};
 use tokio::time;
 
 use toshi_types::{Catalog, IndexHandle};
 
-#[allow(irrefutable_let_patterns)]
-pub async fn watcher<C: Catalog>(cat: Arc<C>, commit_duration: f32, lock: Arc<AtomicBool>) -> Result<(), ()> {
-    while let _ = time::interval(Duration::from_secs_f32(commit_duration)).tick().await {
-        for e in cat.get_collection().iter() {
-            let (k, v) = e.pair();
-            let writer = v.get_writer();
-            let current_ops = v.get_opstamp();
-            if current_ops == 0 {
-                trace!("No update to index={}, opstamp={}", k, current_ops);
-            } else if !lock.load(Ordering::SeqCst) {
-                let mut w = writer.lock().await;
-                trace!("Committing: {}...", k);
-                w.commit().unwrap();
-                v.set_opstamp(0);
-            }
+/// How often each index is checked for pending writes its commit policy says are due
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Commit indexes as their commit policies call for, `commit_duration` is the interval used by indexes whose
+/// policy doesn't have one
+pub async fn watcher<C: Catalog>(cat: Arc<C>, commit_duration: f32) -> Result<(), ()> {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        commit_due_indexes(&*cat, commit_duration).await;
+    }
+}
+
+/// Commit every index that is due a commit and has no bulk session open, a single pass of the watcher
+pub(crate) async fn commit_due_indexes<C: Catalog>(cat: &C, commit_duration: f32) {
+    for e in cat.get_collection().iter() {
+        let (k, v) = e.pair();
+        if v.in_bulk() || !v.commit_due(commit_duration) {
+            trace!("No commit due for index={}, opstamp={}", k, v.get_opstamp());
+            continue;
+        }
+        trace!("Committing: {}...", k);
+        if let Err(e) = v.commit().await {
+            error!("Error committing index {}: {}", k, e);
         }
     }
-    Ok(())
 }
 
 #[cfg(test)]
@@ -33,13 +40,14 @@ pub mod tests {
 
     use crate::handlers::{add_document, all_docs};
     use crate::index::create_test_catalog;
-    use crate::SearchResults;
+    use crate::{AddDocument, SearchResults};
 
     use super::*;
     use http::Response;
     use serde::de::DeserializeOwned;
     use tantivy::schema::*;
     use tantivy::{doc, Index};
+    use toshi_types::IndexConfig;
 
     pub fn create_test_index() -> Index {
         let mut builder = SchemaBuilder::new();
@@ -68,6 +76,21 @@ pub mod tests {
         serde_json::from_slice::<T>(bytes.as_bytes()).unwrap_or_else(|e| panic!("Could not deserialize JSON: {:?}", e))
     }
 
+    /// Poll until `check` holds, failing the test if it doesn't within a few seconds
+    pub async fn wait_until<F, Fut>(what: &str, mut check: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = bool>,
+    {
+        for _ in 0..500 {
+            if check().await {
+                return;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Timed out waiting for {}", what);
+    }
+
     pub fn cmp_float(a: f32, b: f32) -> bool {
         let abs_a = a.abs();
         let abs_b = b.abs();
@@ -88,14 +111,15 @@ pub mod tests {
     #[tokio::test]
     pub async fn test_auto_commit() {
         let catalog = create_test_catalog("test_index");
-        let lock = Arc::new(AtomicBool::new(false));
-        let watcher = watcher(Arc::clone(&catalog), 0.1, Arc::clone(&lock));
+        let watcher = watcher(Arc::clone(&catalog), 0.1);
 
         tokio::spawn(watcher);
 
         let body = r#"{"document": { "test_text": "Babbaboo!", "test_u64": 10 , "test_i64": -10, "test_unindex": "asdf1234" } }"#;
 
-        add_document(Arc::clone(&catalog), Body::from(body), "test_index").await.unwrap();
+        add_document(Arc::clone(&catalog), Body::from(body), "test_index", "")
+            .await
+            .unwrap();
 
         let expected = 6;
         for _ in 0..2 {
@@ -107,4 +131,83 @@ pub mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    pub async fn test_auto_commit_waits_for_bulk() -> Result<(), Box<dyn std::error::Error>> {
+        let catalog = create_test_catalog("test_index");
+        let handle = catalog.get_index("test_index")?;
+
+        let first = handle.begin_bulk()?;
+        let second = handle.begin_bulk()?;
+        let body = r#"{"document": { "test_text": "Babbaboo!", "test_u64": 10 , "test_i64": -10, "test_unindex": "asdf1234" } }"#;
+        add_document(Arc::clone(&catalog), Body::from(body), "test_index", "").await?;
+
+        // With an interval too short to wait out the write is due right away, so only the open session holds it back
+        drop(first);
+        commit_due_indexes(&*catalog, f32::MIN_POSITIVE).await;
+        assert!(handle.in_bulk());
+        assert_eq!(handle.get_opstamp(), 1);
+
+        drop(second);
+        commit_due_indexes(&*catalog, f32::MIN_POSITIVE).await;
+        assert!(!handle.in_bulk());
+        assert_eq!(handle.get_opstamp(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_exclusive_bulk() -> Result<(), Box<dyn std::error::Error>> {
+        let catalog = create_test_catalog("test_index");
+        let handle = catalog.get_index("test_index")?;
+
+        let shared = handle.begin_bulk()?;
+        assert!(handle.begin_exclusive_bulk().is_err());
+        drop(shared);
+
+        let exclusive = handle.begin_exclusive_bulk()?;
+        assert!(handle.begin_bulk().is_err());
+        assert!(handle.begin_exclusive_bulk().is_err());
+        let body = r#"{"document": { "test_text": "Babbaboo!", "test_u64": 10 , "test_i64": -10, "test_unindex": "asdf1234" } }"#;
+        let resp = add_document(Arc::clone(&catalog), Body::from(body), "test_index", "").await?;
+        assert_eq!(resp.status(), hyper::StatusCode::BAD_REQUEST);
+        assert_eq!(handle.get_opstamp(), 0);
+
+        drop(exclusive);
+        assert!(!handle.in_bulk());
+        let _shared = handle.begin_bulk()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_commit_policy_limits() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let settings = crate::settings::Settings {
+            path: format!("{}/", dir.path().display()),
+            ..Default::default()
+        };
+        let catalog = Arc::new(crate::index::IndexCatalog::new(settings)?);
+        let config: IndexConfig = serde_json::from_str(r#"{"commit_policy": {"interval": 0, "max_pending_docs": 2}}"#)?;
+        let mut builder = SchemaBuilder::new();
+        builder.add_text_field("text", TEXT | STORED);
+        catalog.add_index_with_config("limited", builder.build(), config).await?;
+        let handle = catalog.get_index("limited")?;
+
+        let new_doc = || AddDocument::new(serde_json::json!({"text": "Babbaboo!"}), None);
+        handle.add_document(new_doc()).await?;
+        commit_due_indexes(&*catalog, 0.1).await;
+        let status = handle.get_commit_status();
+        assert_eq!(status.pending_docs, 1);
+        assert!(status.pending_bytes > 0);
+        assert_eq!(status.last_commit, None);
+
+        handle.add_document(new_doc()).await?;
+        commit_due_indexes(&*catalog, 0.1).await;
+        let status = handle.get_commit_status();
+        assert_eq!((status.pending_docs, status.pending_bytes), (0, 0));
+        assert!(status.last_commit.is_some());
+
+        drop(handle);
+        catalog.remove_index("limited").await?;
+        Ok(())
+    }
 }
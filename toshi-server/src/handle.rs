@@ -1,25 +1,41 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use log::*;
-use tantivy::collector::{FacetCollector, MultiCollector, TopDocs};
+use tantivy::collector::{Count, DocSetCollector, FacetCollector, MultiCollector, TopDocs};
 use tantivy::directory::MmapDirectory;
 use tantivy::merge_policy::MergePolicy;
-use tantivy::query::{AllQuery, QueryParser};
+use tantivy::query::{AllQuery, BooleanQuery, Query as TantivyQuery, QueryParser, TermQuery};
 use tantivy::schema::*;
 use tantivy::space_usage::SearcherSpaceUsage;
-use tantivy::{Document, Index, IndexReader, IndexWriter, ReloadPolicy, Term};
+use tantivy::{
+    u64_to_f64, u64_to_i64, DocAddress, DocId, Document, Index, IndexReader, IndexWriter, ReloadPolicy, SegmentReader, Term, UserOperation,
+};
 use tokio::sync::*;
 
 use toshi_types::*;
 
 use crate::settings::{Settings, DEFAULT_WRITER_MEMORY};
+use crate::suggest::{complete, suggest};
+use crate::utils::merge_patch;
 use crate::{register_tokenizers, Result};
 use crate::{AddDocument, SearchResults};
 
+/// The file inside an index's directory that holds its [`IndexConfig`]
+pub const INDEX_CONFIG_FILE: &str = "toshi.json";
+
+const READER_RELOAD_POLICY: ReloadPolicy = ReloadPolicy::OnCommit;
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
 /// Index handle that operates on an Index local to the node, a remote index handle
 /// will eventually call to wherever the local index is stored, so at some level the relevant
 /// local handle will always get called through rpc
@@ -30,7 +46,16 @@ pub struct LocalIndex {
     reader: IndexReader,
     current_opstamp: Arc<AtomicUsize>,
     deleted_docs: Arc<AtomicU64>,
+    bulk_sessions: Arc<AtomicUsize>,
+    pending_bytes: Arc<AtomicUsize>,
+    /// Primary keys of the documents written or deleted since the last commit, only changed with the writer locked
+    pending_ids: Arc<std::sync::Mutex<HashSet<Term>>>,
+    last_commit: Arc<AtomicU64>,
+    opened: u64,
     name: String,
+    writer_memory: usize,
+    config: IndexConfig,
+    primary_key: Option<Field>,
 }
 
 impl PartialEq for LocalIndex {
@@ -55,6 +80,49 @@ impl IndexHandle for LocalIndex {
         Arc::clone(&self.writer)
     }
 
+    fn get_config(&self) -> IndexConfig {
+        self.config.clone()
+    }
+
+    fn get_deleted_docs(&self) -> DeletedDocs {
+        let in_segments = self
+            .reader
+            .searcher()
+            .segment_readers()
+            .iter()
+            .map(|segment| u64::from(segment.num_deleted_docs()))
+            .sum();
+        DeletedDocs {
+            total: self.deleted_docs.load(Ordering::SeqCst),
+            in_segments,
+        }
+    }
+
+    async fn get_settings(&self) -> SettingsResponse {
+        let merge_policy = self.writer.lock().await.get_merge_policy();
+        let schema = self.index.schema();
+        let tokenizers = schema
+            .fields()
+            .filter_map(|(_, entry)| match entry.field_type() {
+                FieldType::Str(opts) => opts
+                    .get_indexing_options()
+                    .map(|o| (entry.name().to_string(), o.tokenizer().to_string())),
+                _ => None,
+            })
+            .collect();
+        SettingsResponse {
+            writer_memory: self.writer_memory,
+            merge_policy: format!("{:?}", merge_policy),
+            reload_policy: match READER_RELOAD_POLICY {
+                ReloadPolicy::Manual => "Manual",
+                ReloadPolicy::OnCommit => "OnCommit",
+            }
+            .into(),
+            tokenizers,
+            config: self.config.clone(),
+        }
+    }
+
     fn get_space(&self) -> SearcherSpaceUsage {
         self.reader.searcher().space_usage().unwrap()
     }
@@ -69,9 +137,66 @@ impl IndexHandle for LocalIndex {
         self.current_opstamp.store(opstamp, Ordering::SeqCst)
     }
 
+    fn begin_bulk(&self) -> Result<BulkSession> {
+        BulkSession::open(&self.bulk_sessions).ok_or_else(|| Error::IndexInUse(self.name.clone()))
+    }
+
+    fn begin_exclusive_bulk(&self) -> Result<BulkSession> {
+        BulkSession::open_exclusive(&self.bulk_sessions).ok_or_else(|| Error::IndexInUse(self.name.clone()))
+    }
+
+    fn in_bulk(&self) -> bool {
+        self.bulk_sessions.load(Ordering::SeqCst) > 0
+    }
+
     async fn commit(&self) -> Result<u64> {
         let mut lock = self.writer.lock().await;
-        Ok(lock.commit()?)
+        let opstamp = lock.commit()?;
+        // Searches see the commit before any of its documents stop counting as pending, so they can be read back
+        self.reader.reload()?;
+        // Reset while the writer is still locked so no write can land between the commit and the reset
+        self.set_opstamp(0);
+        self.pending_bytes.store(0, Ordering::SeqCst);
+        self.pending_ids.lock().unwrap().clear();
+        self.last_commit.store(now_millis(), Ordering::SeqCst);
+        Ok(opstamp)
+    }
+
+    async fn rollback(&self) -> Result<()> {
+        let mut lock = self.writer.lock().await;
+        lock.rollback()?;
+        self.set_opstamp(0);
+        self.pending_bytes.store(0, Ordering::SeqCst);
+        self.pending_ids.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn add_pending(&self, docs: usize, bytes: usize) {
+        self.current_opstamp.fetch_add(docs, Ordering::SeqCst);
+        self.pending_bytes.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    fn get_commit_status(&self) -> CommitStatus {
+        CommitStatus {
+            policy: self.config.commit_policy,
+            pending_docs: self.get_opstamp() as u64,
+            pending_bytes: self.pending_bytes.load(Ordering::SeqCst) as u64,
+            last_commit: Some(self.last_commit.load(Ordering::SeqCst)).filter(|ms| *ms > 0),
+        }
+    }
+
+    fn commit_due(&self, default_interval: f32) -> bool {
+        let status = self.get_commit_status();
+        if status.pending_docs == 0 {
+            return false;
+        }
+        let since = status.last_commit.unwrap_or(self.opened);
+        let waited = Duration::from_millis(now_millis().saturating_sub(since));
+        status.policy.over_limit(status.pending_docs, status.pending_bytes)
+            || status
+                .policy
+                .wait(default_interval, (&self.name, since))
+                .is_some_and(|wait| waited >= wait)
     }
 
     async fn search_index(&self, search: Search) -> Result<SearchResults> {
@@ -79,17 +204,29 @@ impl IndexHandle for LocalIndex {
         let schema = self.index.schema();
         let mut multi_collector = MultiCollector::new();
 
-        let sorted_top_handle = search.sort_by.clone().and_then(|sort_by| {
+        let sort_field = search.sort_by.as_deref().and_then(|sort_by| {
             info!("Sorting with: {}", sort_by);
-            if let Some(f) = schema.get_field(&sort_by) {
-                let entry = schema.get_field_entry(f);
-                if entry.is_fast() && entry.is_stored() {
-                    let c = TopDocs::with_limit(search.limit).order_by_u64_field(f);
-                    return Some(multi_collector.add_collector(c));
-                }
-            }
-            None
+            schema.get_field(sort_by).filter(|f| {
+                let entry = schema.get_field_entry(*f);
+                entry.is_fast() && entry.is_stored()
+            })
         });
+        // Sorting is done on the u64 form of fast field values, which keeps their order, and ascending sorts rank
+        // documents by the reverse of it
+        let (desc_handle, asc_handle) = match (sort_field, search.sort_order) {
+            (Some(f), SortOrder::Desc) => (
+                Some(multi_collector.add_collector(TopDocs::with_limit(search.limit).order_by_u64_field(f))),
+                None,
+            ),
+            (Some(f), SortOrder::Asc) => {
+                let c = TopDocs::with_limit(search.limit).custom_score(move |segment: &SegmentReader| {
+                    let values = segment.fast_fields().u64_lenient(f).expect("Sort field is a fast field");
+                    move |doc: DocId| Reverse(values.get_val(doc))
+                });
+                (None, Some(multi_collector.add_collector(c)))
+            }
+            (None, _) => (None, None),
+        };
 
         let top_handle = multi_collector.add_collector(TopDocs::with_limit(search.limit));
         let facet_handle = search.facets.clone().and_then(|f| {
@@ -105,31 +242,25 @@ impl IndexHandle for LocalIndex {
         });
 
         if let Some(query) = search.query {
-            let gen_query = match query {
-                Query::Regex(regex) => regex.create_query(&schema)?,
-                Query::Phrase(phrase) => phrase.create_query(&schema)?,
-                Query::Fuzzy(fuzzy) => fuzzy.create_query(&schema)?,
-                Query::Exact(term) => term.create_query(&schema)?,
-                Query::Range(range) => range.create_query(&schema)?,
-                Query::Boolean { bool } => bool.create_query(&schema)?,
-                Query::Raw { raw } => {
-                    let fields: Vec<Field> = schema.fields().filter_map(|f| schema.get_field(f.1.name())).collect();
-                    let query_parser = QueryParser::for_index(&self.index, fields);
-                    query_parser.parse_query(&raw)?
-                }
-                Query::All => Box::new(AllQuery),
-            };
+            let gen_query = self.build_query(query)?;
 
             trace!("{:?}", gen_query);
             let mut scored_docs = searcher.search(&*gen_query, &multi_collector)?;
 
             // FruitHandle isn't a public type which leads to some duplicate code like this.
-            let docs: Vec<ScoredDoc<FlatNamedDocument>> = if let Some(h) = sorted_top_handle {
-                h.extract(&mut scored_docs)
+            let sorted = match (desc_handle, asc_handle) {
+                (Some(h), _) => Some(h.extract(&mut scored_docs)),
+                (_, Some(h)) => Some(h.extract(&mut scored_docs).into_iter().map(|(Reverse(v), d)| (v, d)).collect()),
+                (None, None) => None,
+            };
+            let docs: Vec<ScoredDoc<FlatNamedDocument>> = if let (Some(sorted), Some(f)) = (sorted, sort_field) {
+                let field_type = schema.get_field_entry(f).field_type();
+                sorted
                     .into_iter()
-                    .map(|(score, doc)| {
+                    .map(|(value, doc)| {
                         let d = searcher.doc(doc).expect("Doc not found in segment");
-                        ScoredDoc::<FlatNamedDocument>::new(Some(score as f32), schema.to_named_doc(&d).into())
+                        ScoredDoc::<FlatNamedDocument>::new(Some(value as f32), schema.to_named_doc(&d).into())
+                            .with_sort_value(LocalIndex::sort_value(field_type, value))
                     })
                     .collect()
             } else {
@@ -159,54 +290,229 @@ impl IndexHandle for LocalIndex {
         }
     }
 
+    async fn count(&self, query: Query) -> Result<DocCount> {
+        let searcher = self.reader.searcher();
+        let gen_query = self.build_query(query)?;
+        let count = searcher.search(&*gen_query, &Count)?;
+        Ok(DocCount { count: count as u64 })
+    }
+
+    async fn suggest(&self, request: SuggestRequest) -> Result<BTreeMap<String, FieldSuggestions>> {
+        let searcher = self.reader.searcher();
+        suggest(&self.index, &searcher, &request)
+    }
+
+    async fn complete(&self, request: CompleteRequest) -> Result<Vec<Completion>> {
+        let searcher = self.reader.searcher();
+        complete(&self.index, &searcher, &request)
+    }
+
     async fn add_document(&self, add_doc: AddDocument) -> Result<()> {
         let index_schema = self.index.schema();
         let writer_lock = self.get_writer();
         {
             let index_writer = writer_lock.lock().await;
-            let doc: Document = LocalIndex::parse_doc(&index_schema, &add_doc.document.to_string())?;
-            index_writer.add_document(doc)?;
+            self.check_writable()?;
+            let json = add_doc.document.to_string();
+            let doc: Document = LocalIndex::parse_doc(&index_schema, &json)?;
+            self.write_document(&index_writer, doc)?;
+            self.add_pending(1, json.len());
         }
-        if let Some(opts) = add_doc.options {
-            if opts.commit {
-                let mut commit_writer = writer_lock.lock().await;
-                commit_writer.commit()?;
-                self.set_opstamp(0);
-            } else {
-                self.set_opstamp(self.get_opstamp() + 1);
+        self.finish_write(add_doc.options).await
+    }
+
+    async fn get_document(&self, id: &str) -> Result<Option<FlatNamedDocument>> {
+        let (term, _) = self.parse_id(id)?;
+        let searcher = self.reader.searcher();
+        let query = TermQuery::new(term, IndexRecordOption::Basic);
+        match searcher.search(&query, &TopDocs::with_limit(1))?.first() {
+            Some((_, address)) => {
+                let doc = searcher.doc(*address)?;
+                Ok(Some(self.index.schema().to_named_doc(&doc).into()))
             }
-        } else {
-            self.set_opstamp(self.get_opstamp() + 1);
+            None => Ok(None),
         }
-        Ok(())
     }
 
-    async fn delete_term(&self, term: DeleteDoc) -> Result<DocsAffected> {
-        let index_schema = self.index.schema();
+    async fn upsert_document(&self, id: &str, add_doc: AddDocument) -> Result<()> {
+        let (term, id_value) = self.parse_id(id)?;
+        let key_name = self.index.schema().get_field_name(term.field()).to_string();
+        let mut document = add_doc.document;
+        match document.as_object_mut() {
+            Some(fields) => fields.insert(key_name, id_value),
+            None => return Err(Error::QueryError("Document must be a JSON object".into())),
+        };
+
         let writer_lock = self.get_writer();
-        let before: u64;
         {
             let index_writer = writer_lock.lock().await;
-            before = self.reader.searcher().num_docs();
+            self.check_writable()?;
+            self.replace_document(&index_writer, term, &document.to_string())?;
+        }
+        self.finish_write(add_doc.options).await
+    }
+
+    async fn update_document(&self, id: &str, patch: AddDocument) -> Result<serde_json::Value> {
+        let (term, id_value) = self.parse_id(id)?;
+        let index_schema = self.index.schema();
+        let key_name = index_schema.get_field_name(term.field()).to_string();
+
+        // The document is read and written back under one lock, so no other write to it can land in between
+        let writer_lock = self.get_writer();
+        let document = {
+            let index_writer = writer_lock.lock().await;
+            self.check_writable()?;
+            // Reads only see the last commit, patching it would throw away the writes made since
+            if self.pending_ids.lock().unwrap().contains(&term) {
+                return Err(Error::DocumentPending(id.into()));
+            }
+            let stored = self.get_document(id).await?.ok_or_else(|| Error::UnknownDocument(id.into()))?;
+            let mut document = serde_json::Value::Object(stored.0.into_iter().collect());
+            merge_patch(&mut document, &patch.document);
+            if let Some(fields) = document.as_object_mut() {
+                fields.insert(key_name, id_value);
+            }
+            LocalIndex::check_unstored(&index_schema, |name| document.get(name).is_some())?;
+            self.replace_document(&index_writer, term, &document.to_string())?;
+            document
+        };
+        self.finish_write(patch.options).await?;
+        Ok(document)
+    }
+
+    async fn update_by_query(&self, request: UpdateByQuery, progress: Arc<TaskProgress>) -> Result<()> {
+        // Rewritten documents replace the old ones by their primary key. Without one the old documents could only be
+        // deleted through the query, which would also take any matching document written after the search.
+        if self.primary_key.is_none() {
+            return Err(Error::MissingPrimaryKey(self.name.clone()));
+        }
+        let schema = self.index.schema();
+        LocalIndex::check_unstored(&schema, |name| request.set.get(name).is_some_and(|v| !v.is_null()))?;
+        let query = self.build_query(request.query)?;
+        let searcher = self.reader.searcher();
+        let mut addresses: Vec<DocAddress> = searcher.search(&query, &DocSetCollector)?.into_iter().collect();
+        addresses.sort();
+        progress.set_total(addresses.len() as u64);
 
-            for (field, value) in term.terms {
-                if let Some(f) = index_schema.get_field(&field) {
-                    let term = Term::from_field_text(f, &value);
-                    index_writer.delete_term(term);
+        // Each batch is written as soon as it's parsed, a bad assignment stops the update at the batch it's found in
+        let patch = serde_json::Value::Object(request.set);
+        let rewritten = async {
+            for batch in addresses.chunks(request.batch_size.max(1)) {
+                if progress.is_cancelled() {
+                    return Err(Error::TaskCancelled);
                 }
+                let mut parsed = Vec::with_capacity(batch.len());
+                for address in batch {
+                    let mut document = LocalIndex::stored_json(&schema, &searcher.doc(*address)?);
+                    progress.add_read(1);
+                    merge_patch(&mut document, &patch);
+                    let json = document.to_string();
+                    parsed.push((LocalIndex::parse_doc(&schema, &json)?, json.len()));
+                }
+                self.write_batch(parsed, &progress).await?;
             }
+            Ok(())
         }
-        if let Some(opts) = term.options {
-            if opts.commit {
-                let mut commit_writer = writer_lock.lock().await;
-                commit_writer.commit()?;
-                self.set_opstamp(0);
+        .await;
+        let finished = self.finish_write(request.options).await;
+        rewritten.and(finished)
+    }
+
+    async fn reindex(&self, dest: Self, request: ReindexRequest, progress: Arc<TaskProgress>) -> Result<()> {
+        let query = match request.query {
+            Some(query) => self.build_query(query)?,
+            None => Box::new(AllQuery),
+        };
+        let searcher = self.reader.searcher();
+        let mut addresses: Vec<DocAddress> = searcher.search(&query, &DocSetCollector)?.into_iter().collect();
+        addresses.sort();
+        progress.set_total(addresses.len() as u64);
+
+        // Each batch is written to the destination as soon as it's read, documents it can't take are counted as errors
+        let schema = self.index.schema();
+        let dest_schema = dest.index.schema();
+        let copied = async {
+            for batch in addresses.chunks(request.batch_size.max(1)) {
+                if progress.is_cancelled() {
+                    return Err(Error::TaskCancelled);
+                }
+                let mut parsed = Vec::with_capacity(batch.len());
+                for address in batch {
+                    let document = LocalIndex::stored_json(&schema, &searcher.doc(*address)?);
+                    progress.add_read(1);
+                    let mapped: serde_json::Map<String, serde_json::Value> = match document {
+                        serde_json::Value::Object(fields) => fields
+                            .into_iter()
+                            .map(|(name, value)| (request.fields.get(&name).cloned().unwrap_or(name), value))
+                            .collect(),
+                        _ => continue,
+                    };
+                    let json = serde_json::Value::Object(mapped).to_string();
+                    match LocalIndex::parse_doc(&dest_schema, &json) {
+                        Ok(doc) => parsed.push((doc, json.len())),
+                        Err(e) => {
+                            debug!("Could not reindex document {:?} into {}: {}", address, dest.name, e);
+                            progress.add_errors(1);
+                        }
+                    }
+                }
+                dest.write_batch(parsed, &progress).await?;
             }
+            Ok(())
         }
-        let docs_affected = before - self.reader.searcher().num_docs();
-        let current = self.deleted_docs.load(Ordering::SeqCst);
-        self.deleted_docs.store(current + docs_affected, Ordering::SeqCst);
-        Ok(DocsAffected { docs_affected })
+        .await;
+        let finished = dest.finish_write(request.options).await;
+        copied.and(finished)
+    }
+
+    async fn delete_by_query(&self, request: DeleteByQuery) -> Result<DocsAffected> {
+        let query = self.build_query(request.query)?;
+        let writer_lock = self.get_writer();
+        let docs_affected = {
+            let index_writer = writer_lock.lock().await;
+            self.check_writable()?;
+            let docs_affected = self.reader.searcher().search(&query, &Count)? as u64;
+            index_writer.delete_query(query)?;
+            docs_affected
+        };
+        self.finish_delete(docs_affected, request.options).await
+    }
+
+    async fn delete_document(&self, id: &str, options: Option<IndexOptions>) -> Result<DocsAffected> {
+        let (term, _) = self.parse_id(id)?;
+        let matching = TermQuery::new(term.clone(), IndexRecordOption::Basic);
+        let writer_lock = self.get_writer();
+        let docs_affected = {
+            let index_writer = writer_lock.lock().await;
+            self.check_writable()?;
+            let docs_affected = self.reader.searcher().search(&matching, &Count)? as u64;
+            self.pending_ids.lock().unwrap().insert(term.clone());
+            index_writer.delete_term(term);
+            docs_affected
+        };
+        self.finish_delete(docs_affected, options).await
+    }
+
+    async fn delete_term(&self, term: DeleteDoc) -> Result<DocsAffected> {
+        let index_schema = self.index.schema();
+        let terms: Vec<Term> = term
+            .terms
+            .iter()
+            .filter_map(|(field, value)| index_schema.get_field(field).map(|f| Term::from_field_text(f, value)))
+            .collect();
+        let matching = BooleanQuery::new_multiterms_query(terms.clone());
+
+        let writer_lock = self.get_writer();
+        let docs_affected = {
+            let index_writer = writer_lock.lock().await;
+            self.check_writable()?;
+            let docs_affected = self.reader.searcher().search(&matching, &Count)? as u64;
+            for term in terms {
+                index_writer.delete_term(term);
+            }
+            docs_affected
+        };
+        self.finish_delete(docs_affected, term.options).await
     }
 }
 
@@ -217,11 +523,15 @@ impl LocalIndex {
         schema: Schema,
         writer_memory: usize,
         merge_policy: Box<dyn MergePolicy>,
+        config: IndexConfig,
     ) -> Result<Self> {
+        let primary_key = LocalIndex::primary_key_field(&schema, &config)?;
+        config.commit_policy.validate()?;
         base_path.push(index_name);
         if !base_path.exists() {
             fs::create_dir(&base_path)?;
         }
+        fs::write(base_path.join(INDEX_CONFIG_FILE), serde_json::to_vec_pretty(&config)?)?;
         let dir = MmapDirectory::open(base_path)?;
         let index = Index::open_or_create(dir, schema)?;
         let index = register_tokenizers(index);
@@ -229,14 +539,22 @@ impl LocalIndex {
         i.set_merge_policy(merge_policy);
         let current_opstamp = Arc::new(AtomicUsize::new(0));
         let writer = Arc::new(Mutex::new(i));
-        let reader = index.reader_builder().reload_policy(ReloadPolicy::OnCommit).try_into()?;
+        let reader = index.reader_builder().reload_policy(READER_RELOAD_POLICY).try_into()?;
         Ok(Self {
             index,
             reader,
             writer,
             current_opstamp,
             deleted_docs: Arc::new(AtomicU64::new(0)),
+            bulk_sessions: Arc::new(AtomicUsize::new(0)),
+            pending_bytes: Arc::new(AtomicUsize::new(0)),
+            pending_ids: Arc::default(),
+            last_commit: Arc::new(AtomicU64::new(0)),
+            opened: now_millis(),
             name: index_name.into(),
+            writer_memory,
+            config,
+            primary_key,
         })
     }
 
@@ -245,17 +563,206 @@ impl LocalIndex {
         i.set_merge_policy(Settings::default().get_merge_policy());
         let current_opstamp = Arc::new(AtomicUsize::new(0));
         let writer = Arc::new(Mutex::new(i));
-        let reader = index.reader_builder().reload_policy(ReloadPolicy::OnCommit).try_into()?;
+        let reader = index.reader_builder().reload_policy(READER_RELOAD_POLICY).try_into()?;
         Ok(Self {
             index,
             reader,
             writer,
             current_opstamp,
             deleted_docs: Arc::new(AtomicU64::new(0)),
+            bulk_sessions: Arc::new(AtomicUsize::new(0)),
+            pending_bytes: Arc::new(AtomicUsize::new(0)),
+            pending_ids: Arc::default(),
+            last_commit: Arc::new(AtomicU64::new(0)),
+            opened: now_millis(),
             name,
+            writer_memory: DEFAULT_WRITER_MEMORY,
+            config: IndexConfig::default(),
+            primary_key: None,
+        })
+    }
+
+    /// Take the writer out of the handle, giving the handle back while other clones of it still share the writer
+    pub(crate) fn try_into_writer(self) -> std::result::Result<IndexWriter, Box<Self>> {
+        match Arc::try_unwrap(self.writer) {
+            Ok(writer) => Ok(writer.into_inner()),
+            Err(writer) => Err(Box::new(Self { writer, ..self })),
+        }
+    }
+
+    /// Read the config kept in an index's directory, indexes created before configs existed get the default one
+    pub fn read_config(index_path: &Path) -> Result<IndexConfig> {
+        let path = index_path.join(INDEX_CONFIG_FILE);
+        if path.exists() {
+            Ok(serde_json::from_slice(&fs::read(path)?)?)
+        } else {
+            Ok(IndexConfig::default())
+        }
+    }
+
+    pub(crate) fn primary_key_field(schema: &Schema, config: &IndexConfig) -> Result<Option<Field>> {
+        let name = match &config.primary_key {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let field = schema
+            .get_field(name)
+            .ok_or_else(|| Error::InvalidConfig(format!("Primary key {} is not a field in the schema", name)))?;
+        let entry = schema.get_field_entry(field);
+        let valid_type = match entry.field_type() {
+            FieldType::Str(opts) => opts.get_indexing_options().map(|o| o.tokenizer() == "raw").unwrap_or(false),
+            FieldType::U64(_) | FieldType::I64(_) => entry.is_indexed(),
+            _ => false,
+        };
+        if valid_type && entry.is_stored() {
+            Ok(Some(field))
+        } else {
+            Err(Error::InvalidConfig(format!(
+                "Primary key {} must be a stored and indexed u64, i64 or text field using the raw tokenizer",
+                name
+            )))
+        }
+    }
+
+    /// Turn a document id into the term of the primary key it refers to, along with the id as the JSON value
+    /// a document would have in that field
+    fn parse_id(&self, id: &str) -> Result<(Term, serde_json::Value)> {
+        let field = self.primary_key.ok_or_else(|| Error::MissingPrimaryKey(self.name.clone()))?;
+        let bad_id = |_| Error::QueryError(format!("Invalid id: {}", id));
+        match self.index.schema().get_field_entry(field).field_type() {
+            FieldType::U64(_) => {
+                let id = id.parse::<u64>().map_err(bad_id)?;
+                Ok((Term::from_field_u64(field, id), id.into()))
+            }
+            FieldType::I64(_) => {
+                let id = id.parse::<i64>().map_err(bad_id)?;
+                Ok((Term::from_field_i64(field, id), id.into()))
+            }
+            _ => Ok((Term::from_field_text(field, id), id.into())),
+        }
+    }
+
+    /// Fail while a bulk insert holds this index exclusively, since its rollback would throw away the write. This is
+    /// checked with the writer locked so no write can slip in after the insert took the index.
+    fn check_writable(&self) -> Result<()> {
+        if BulkSession::is_held_exclusively(&self.bulk_sessions) {
+            return Err(Error::IndexInUse(self.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Fail when a rebuilt document would be missing fields that are indexed but never stored, `given` tells
+    /// whether a field's value is being supplied some other way
+    fn check_unstored(schema: &Schema, given: impl Fn(&str) -> bool) -> Result<()> {
+        let missing: Vec<&str> = schema
+            .fields()
+            .filter(|(_, entry)| entry.is_indexed() && !entry.is_stored())
+            .map(|(_, entry)| entry.name())
+            .filter(|name| !given(name))
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::UnstoredFields(missing.join(", ")))
+        }
+    }
+
+    /// The stored fields of a document as a JSON object
+    fn stored_json(schema: &Schema, doc: &Document) -> serde_json::Value {
+        let named = FlatNamedDocument::from(schema.to_named_doc(doc));
+        serde_json::Value::Object(named.0.into_iter().collect())
+    }
+
+    /// The typed value of a fast field from the u64 form it's sorted by
+    fn sort_value(field_type: &FieldType, value: u64) -> SortValue {
+        match field_type {
+            FieldType::I64(_) | FieldType::Date(_) => SortValue::I64(u64_to_i64(value)),
+            FieldType::F64(_) => SortValue::F64(u64_to_f64(value)),
+            _ => SortValue::U64(value),
+        }
+    }
+
+    /// Add a document, when the index has a primary key any document with the same key is replaced by it
+    fn write_document(&self, writer: &IndexWriter, doc: Document) -> Result<()> {
+        let key = self.primary_key.and_then(|field| match doc.get_first(field) {
+            Some(Value::Str(s)) => Some(Term::from_field_text(field, s)),
+            Some(Value::U64(u)) => Some(Term::from_field_u64(field, *u)),
+            Some(Value::I64(i)) => Some(Term::from_field_i64(field, *i)),
+            _ => None,
+        });
+        match key {
+            Some(term) => {
+                self.pending_ids.lock().unwrap().insert(term.clone());
+                writer.run(vec![UserOperation::Delete(term), UserOperation::Add(doc)])?
+            }
+            None => writer.add_document(doc)?,
+        };
+        Ok(())
+    }
+
+    /// Replace the document with the given primary key by a JSON document, `writer` being the locked writer
+    fn replace_document(&self, writer: &IndexWriter, term: Term, json: &str) -> Result<()> {
+        let doc = LocalIndex::parse_doc(&self.index.schema(), json)?;
+        self.pending_ids.lock().unwrap().insert(term.clone());
+        writer.run(vec![UserOperation::Delete(term), UserOperation::Add(doc)])?;
+        self.add_pending(1, json.len());
+        Ok(())
+    }
+
+    /// Commit a delete if asked to, the count of affected documents was taken from the last commit so
+    /// without one the delete is reported as pending
+    async fn finish_delete(&self, docs_affected: u64, options: Option<IndexOptions>) -> Result<DocsAffected> {
+        let commit = options.map(|o| o.commit).unwrap_or(false);
+        if commit {
+            self.commit().await?;
+        }
+        self.deleted_docs.fetch_add(docs_affected, Ordering::SeqCst);
+        Ok(DocsAffected {
+            docs_affected,
+            pending: !commit,
         })
     }
 
+    /// Write a batch of parsed documents, along with the length of the JSON each was parsed from, under one lock
+    async fn write_batch(&self, batch: Vec<(Document, usize)>, progress: &TaskProgress) -> Result<()> {
+        let writer_lock = self.get_writer();
+        let index_writer = writer_lock.lock().await;
+        for (doc, len) in batch {
+            self.write_document(&index_writer, doc)?;
+            self.add_pending(1, len);
+            progress.add_written(1);
+        }
+        Ok(())
+    }
+
+    /// Commit a write if asked to, otherwise it waits on the commit watcher. Writes are counted as pending when
+    /// they're made, while the writer is still locked.
+    async fn finish_write(&self, options: Option<IndexOptions>) -> Result<()> {
+        if options.map(|o| o.commit).unwrap_or(false) {
+            self.commit().await?;
+        }
+        Ok(())
+    }
+
+    fn build_query(&self, query: Query) -> Result<Box<dyn TantivyQuery>> {
+        let schema = self.index.schema();
+        let gen_query = match query {
+            Query::Regex(regex) => regex.create_query(&schema)?,
+            Query::Phrase(phrase) => phrase.create_query(&schema)?,
+            Query::Fuzzy(fuzzy) => fuzzy.create_query(&schema)?,
+            Query::Exact(term) => term.create_query(&schema)?,
+            Query::Range(range) => range.create_query(&schema)?,
+            Query::Boolean { bool } => bool.create_query(&schema)?,
+            Query::Raw { raw } => {
+                let fields: Vec<Field> = schema.fields().filter_map(|f| schema.get_field(f.1.name())).collect();
+                let query_parser = QueryParser::for_index(&self.index, fields);
+                query_parser.parse_query(&raw)?
+            }
+            Query::All => Box::new(AllQuery),
+        };
+        Ok(gen_query)
+    }
+
     fn parse_doc(schema: &Schema, bytes: &str) -> Result<Document> {
         schema.parse_document(bytes).map_err(Into::into)
     }
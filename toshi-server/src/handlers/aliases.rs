@@ -0,0 +1,24 @@
+use std::sync::Arc;
+
+use hyper::body::to_bytes;
+use hyper::{Body, StatusCode};
+
+use toshi_types::{Catalog, UpdateAliases};
+
+use crate::handlers::ResponseFuture;
+use crate::utils::{empty_with_code, error_response, with_body};
+
+pub async fn list_aliases<C: Catalog>(catalog: Arc<C>) -> ResponseFuture {
+    Ok(with_body(catalog.get_aliases()))
+}
+
+pub async fn update_aliases<C: Catalog>(catalog: Arc<C>, body: Body) -> ResponseFuture {
+    let agg_body = to_bytes(body).await?;
+    match serde_json::from_slice::<UpdateAliases>(&agg_body) {
+        Ok(update) => match catalog.update_aliases(update.actions) {
+            Ok(_) => Ok(empty_with_code(StatusCode::OK)),
+            Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+        },
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e.into())),
+    }
+}
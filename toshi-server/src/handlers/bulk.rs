@@ -1,147 +1,889 @@
 use bytes::BytesMut;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
-use flume::{unbounded, Receiver, Sender};
+use flume::{bounded, unbounded, Receiver, Sender};
 use futures::StreamExt;
 use hyper::Body;
 use hyper::StatusCode;
 
 use log::*;
-use tantivy::schema::Schema;
-use tantivy::{Document, IndexWriter};
-use tokio::sync::Mutex;
-use tokio::time::timeout;
+use tantivy::schema::{FieldType, Schema};
+use tantivy::Document;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use tokio_util::codec::{Decoder, LinesCodec, LinesCodecError};
+use tokio_util::io::ReaderStream;
 
-use toshi_types::{Catalog, Error, IndexHandle};
+use toshi_types::{
+    BulkAction, BulkActionsResponse, BulkItemResult, BulkLineError, BulkOptions, BulkResponse, BulkSession, Catalog, DeleteDoc, Error,
+    IndexHandle, IndexOptions, OnError, Pipeline, TaskCreated, TaskProgress, WriteOptions,
+};
 
 use crate::handlers::ResponseFuture;
-use crate::utils::{empty_with_code, error_response, not_found};
+use crate::tasks::TaskRegistry;
+use crate::utils::{error_response, merge_patch, not_found, with_body};
+use crate::AddDocument;
 
-const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+/// The most documents added to an index each time its writer is locked
+const WRITE_BATCH_SIZE: u64 = 1000;
 
-async fn index_documents(iw: Arc<Mutex<IndexWriter>>, dr: Receiver<Document>, wr: Arc<AtomicBool>) -> Result<(), Error> {
+/// The most records handed to the parser threads at once
+const PARSE_CHUNK_SIZE: usize = 256;
+
+/// Create the channel between two stages of a bulk insert, which is always bounded so a stage that falls behind
+/// holds back the ones before it. A size of 0 is taken as 1.
+fn channel<T>(size: usize) -> (Sender<T>, Receiver<T>) {
+    bounded(size.max(1))
+}
+
+/// Add parsed documents, along with the size of the record each came from, to an index until the parsers are done
+async fn index_documents<H: IndexHandle>(handle: H, dr: Receiver<(Document, usize)>, progress: Arc<TaskProgress>) -> Result<u64, Error> {
     let start = Instant::now();
-    while let Ok(Ok(doc)) = timeout(DEFAULT_TIMEOUT, dr.recv_async()).await {
+    let iw = handle.get_writer();
+    let mut indexed = 0;
+    while let Ok(first) = dr.recv_async().await {
+        if progress.is_cancelled() {
+            break;
+        }
+        // Take the lock once for the document that woke us up and whatever else is already waiting
         let w = iw.lock().await;
-        w.add_document(doc)?;
+        let (mut batch, mut bytes, mut written) = (0, 0, Ok(()));
+        let mut next = Some(first);
+        while let Some((doc, len)) = next.take() {
+            written = w.add_document(doc).map(|_| ());
+            if written.is_err() {
+                break;
+            }
+            batch += 1;
+            bytes += len;
+            if batch < WRITE_BATCH_SIZE {
+                next = dr.try_recv().ok();
+            }
+        }
+        // Counted before the writer is unlocked, so a commit can't reset the counts in between the writes and their count
+        handle.add_pending(batch as usize, bytes);
+        drop(w);
+        indexed += batch;
+        progress.add_written(batch);
+        written?;
     }
 
     info!("Piping Documents took: {:?}", start.elapsed());
-    wr.store(false, Ordering::SeqCst);
-    Ok(())
+    Ok(indexed)
 }
 
-async fn parsing_documents(s: Schema, ds: Sender<Document>, lr: Receiver<String>, ec: Sender<Error>) -> Result<(), ()> {
-    while let Ok(Ok(line)) = timeout(DEFAULT_TIMEOUT, lr.recv_async()).await {
-        if !line.is_empty() {
-            match s.parse_document(&line) {
-                Ok(doc) => {
-                    info!("Piped document... {}", doc.len());
-                    ds.send_async(doc).await.expect("Parsing Thread failed.");
+/// The layout of the records of a bulk insert
+#[derive(Clone)]
+enum RecordFormat {
+    Json,
+    Delimited {
+        columns: Vec<String>,
+        delimiter: char,
+        quote: char,
+    },
+}
+
+/// How the records of a bulk insert are turned into documents
+struct RecordParser {
+    format: RecordFormat,
+    pipeline: Option<Pipeline>,
+}
+
+impl RecordParser {
+    fn parse(&self, schema: &Schema, record: &str) -> Result<Document, String> {
+        if let (RecordFormat::Json, None) = (&self.format, &self.pipeline) {
+            return schema.parse_document(record).map_err(|e| e.to_string());
+        }
+        let mut doc = match &self.format {
+            RecordFormat::Json => serde_json::from_str(record).map_err(|e| e.to_string())?,
+            RecordFormat::Delimited { columns, delimiter, quote } => {
+                let values = split_record(record, *delimiter, *quote)?;
+                if values.len() != columns.len() {
+                    return Err(format!("Expected {} columns but found {}", columns.len(), values.len()));
                 }
-                Err(e) => {
-                    let err = anyhow::Error::msg("Error parsing document").context(line).context(e);
-                    ec.send_async(Error::TantivyError(err)).await.expect("Parsing thread loop failed.");
+                let mut doc = serde_json::Map::new();
+                for (column, value) in columns.iter().zip(values) {
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let value = match schema.get_field(column) {
+                        Some(field) => coerce_value(schema.get_field_entry(field).field_type(), &value)
+                            .map_err(|e| format!("Column '{}': {}", column, e))?,
+                        // Columns the schema doesn't know about may still be renamed by a pipeline
+                        None => serde_json::Value::String(value),
+                    };
+                    doc.insert(column.clone(), value);
+                }
+                serde_json::Value::Object(doc)
+            }
+        };
+        if let Some(pipeline) = &self.pipeline {
+            pipeline.run(&mut doc).map_err(|e| e.to_string())?;
+        }
+        schema.parse_document(&doc.to_string()).map_err(|e| e.to_string())
+    }
+}
+
+/// Split a csv or tsv record into its columns. Quoted columns may hold delimiters and newlines, and a doubled quote
+/// inside of one stands in for a single quote character
+fn split_record(record: &str, delimiter: char, quote: char) -> Result<Vec<String>, String> {
+    match scan_record(record, delimiter, quote) {
+        (_, true) => Err("Unterminated quoted column".into()),
+        (columns, false) => Ok(columns),
+    }
+}
+
+/// The columns of a csv or tsv record, along with whether it ends inside of a quoted column. A quote only opens a
+/// column it starts, anywhere else it's part of the column.
+fn scan_record(record: &str, delimiter: char, quote: char) -> (Vec<String>, bool) {
+    let mut columns = Vec::new();
+    let mut column = String::new();
+    let mut chars = record.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c == quote {
+                if chars.peek() == Some(&quote) {
+                    column.push(quote);
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            } else {
+                column.push(c);
+            }
+        } else if c == quote && column.is_empty() {
+            quoted = true;
+        } else if c == delimiter {
+            columns.push(std::mem::take(&mut column));
+        } else {
+            column.push(c);
+        }
+    }
+    columns.push(column);
+    (columns, quoted)
+}
+
+/// Turn a column into the JSON value the schema expects for its field
+fn coerce_value(field_type: &FieldType, value: &str) -> Result<serde_json::Value, String> {
+    let invalid = |kind: &str| format!("'{}' is not a valid {}", value, kind);
+    match field_type {
+        FieldType::I64(_) => value.parse::<i64>().map(Into::into).map_err(|_| invalid("i64")),
+        FieldType::U64(_) => value.parse::<u64>().map(Into::into).map_err(|_| invalid("u64")),
+        FieldType::F64(_) => value.parse::<f64>().map(Into::into).map_err(|_| invalid("f64")),
+        FieldType::Bool(_) => value.parse::<bool>().map(Into::into).map_err(|_| invalid("bool")),
+        FieldType::Date(_) => Ok(value.parse::<i64>().map(Into::into).unwrap_or_else(|_| value.into())),
+        FieldType::JsonObject(_) => serde_json::from_str(value).map_err(|e| e.to_string()),
+        _ => Ok(value.into()),
+    }
+}
+
+/// Gathers the lines of a csv or tsv body into whole records, a record continues onto the next line while it has
+/// an open quoted column
+struct DelimitedRecords {
+    delimiter: char,
+    quote: char,
+    pending: Option<(usize, String)>,
+}
+
+impl DelimitedRecords {
+    fn new(delimiter: char, quote: char) -> Self {
+        Self {
+            delimiter,
+            quote,
+            pending: None,
+        }
+    }
+
+    fn is_complete(&self, record: &str) -> bool {
+        !scan_record(record, self.delimiter, self.quote).1
+    }
+
+    fn push(&mut self, line_number: usize, line: &str) -> Option<(usize, String)> {
+        let (start, record) = match self.pending.take() {
+            Some((start, mut record)) => {
+                record.push('\n');
+                record.push_str(line);
+                (start, record)
+            }
+            None if line.trim().is_empty() => return None,
+            None => (line_number, line.to_string()),
+        };
+        if self.is_complete(&record) {
+            Some((start, record))
+        } else {
+            self.pending = Some((start, record));
+            None
+        }
+    }
+
+    /// Whatever is left when the body ends, which can only be a record with an unterminated quote
+    fn finish(&mut self) -> Option<(usize, String)> {
+        self.pending.take()
+    }
+}
+
+/// A chunk of records handed to the parser threads
+type ParseJob = Box<dyn FnOnce() + Send>;
+
+/// The threads bulk inserts parse their records on. Parsing is CPU bound and would otherwise hold up the runtime's
+/// workers or the blocking pool other requests share, so it gets threads of its own, and they're shared by every
+/// insert so there are only ever `json_parsing_threads` of them however many inserts are running.
+pub struct ParserPool {
+    jobs: Sender<ParseJob>,
+}
+
+impl ParserPool {
+    /// Start the given number of parser threads, at least one. They stop once the pool is dropped and the chunks
+    /// already handed to them are parsed.
+    pub fn new(threads: usize) -> Self {
+        let threads = threads.max(1);
+        // Only a couple of chunks wait on each thread, past that inserts wait to hand over more
+        let (jobs, queue) = bounded::<ParseJob>(threads * 2);
+        info!("Spawning {} parsing threads...", threads);
+        for _ in 0..threads {
+            let queue = queue.clone();
+            std::thread::Builder::new()
+                .name("toshi-bulk-parser".into())
+                .spawn(move || {
+                    for job in queue.iter() {
+                        if std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).is_err() {
+                            error!("A bulk insert parser panicked, the records it was parsing were dropped");
+                        }
+                    }
+                })
+                .expect("Could not start a bulk insert parser thread");
+        }
+        Self { jobs }
+    }
+
+    /// Hand a job to the parser threads, waiting while they all have chunks waiting on them
+    async fn run(&self, job: impl FnOnce() + Send + 'static) -> Result<(), Error> {
+        self.jobs.send_async(Box::new(job)).await.map_err(|_| Error::SpawnError)
+    }
+}
+
+/// Parse a chunk of records into documents, stopping early once the insert is cancelled or, when it rolls back on
+/// errors, once any record of it failed to parse
+fn parse_records(
+    s: &Schema,
+    parser: &RecordParser,
+    records: Vec<(usize, String)>,
+    ds: &Sender<(Document, usize)>,
+    ec: &Sender<BulkLineError>,
+    on_error: OnError,
+    progress: &TaskProgress,
+) {
+    for (line_number, line) in records {
+        if progress.is_cancelled() || (on_error == OnError::Rollback && !ec.is_empty()) {
+            break;
+        }
+        match parser.parse(s, &line) {
+            Ok(doc) => {
+                trace!("Piped document... {}", doc.len());
+                if ds.send((doc, line.len())).is_err() {
+                    // The indexer stopped, so nothing more can be written
                     break;
                 }
-            };
+            }
+            Err(message) => {
+                let err = BulkLineError {
+                    line: line_number,
+                    message,
+                };
+                progress.add_errors(1);
+                let _ = ec.send(err);
+            }
         }
     }
-    Ok(())
 }
 
+/// Everything a bulk insert needs once its request has been checked, so the insert can run either while the client
+/// waits or as a background task
+struct BulkIngest<H: IndexHandle> {
+    index: String,
+    handle: H,
+    /// Keeps the index from being auto committed until the insert ends, however it ends. An insert that rolls back
+    /// on errors holds the index exclusively so the rollback only throws away its own writes.
+    _session: BulkSession,
+    schema: Schema,
+    options: BulkOptions,
+    pipeline: Option<Pipeline>,
+    format: Option<RecordFormat>,
+    parsers: Arc<ParserPool>,
+    buffer_size: usize,
+    max_line_length: usize,
+    progress: Arc<TaskProgress>,
+}
+
+impl<H: IndexHandle + Send + Sync + 'static> BulkIngest<H> {
+    /// Throw away everything written to the index that hasn't been committed yet, which the exclusive session
+    /// limits to this insert's writes
+    async fn rollback(&self) -> Result<(), Error> {
+        self.handle.rollback().await
+    }
+
+    /// Hand a chunk of records to the shared parser threads, which send what they parse on to the indexer
+    async fn parse(
+        &self,
+        parser: &Arc<RecordParser>,
+        records: Vec<(usize, String)>,
+        docs: &Sender<(Document, usize)>,
+        errors: &Sender<BulkLineError>,
+    ) -> Result<(), Error> {
+        let schema = self.schema.clone();
+        let parser = Arc::clone(parser);
+        let (docs, errors) = (docs.clone(), errors.clone());
+        let on_error = self.options.on_error;
+        let progress = Arc::clone(&self.progress);
+        self.parsers
+            .run(move || parse_records(&schema, &parser, records, &docs, &errors, on_error, &progress))
+            .await
+    }
+
+    async fn run(self, mut body: Body) -> Result<BulkResponse, Error> {
+        let options = &self.options;
+        let progress = &self.progress;
+        let max_line_length = self.max_line_length;
+        let delimiter = options.delimiter();
+        let mut records = delimiter.map(|delimiter| DelimitedRecords::new(delimiter, options.quote));
+
+        // A bounded channel pushes back on the parsers when indexing falls behind, and they in turn on the reader, so
+        // the body is only read as fast as it can be written instead of piling up in memory. The indexer stops once
+        // the reader and every chunk handed to the parsers are done with their senders.
+        let (doc_sender, doc_recv) = channel::<(Document, usize)>(self.buffer_size);
+        let (err_snd, err_rcv) = unbounded();
+        let indexer = tokio::spawn(index_documents(self.handle.clone(), doc_recv, Arc::clone(progress)));
+
+        // Records can only be parsed once their format is known, which for csv with a header is after the first one
+        let new_parser = |format: RecordFormat| {
+            Arc::new(RecordParser {
+                format,
+                pipeline: self.pipeline.clone(),
+            })
+        };
+        let mut parser = self.format.clone().map(new_parser);
+        let mut chunk = Vec::new();
+
+        let mut buf = BytesMut::new();
+        let mut decoder = if max_line_length > 0 {
+            LinesCodec::new_with_max_length(max_line_length)
+        } else {
+            LinesCodec::new()
+        };
+
+        // Lines are only split on newlines while the body is still arriving, a line cut off at the end of a chunk
+        // is completed by the next one. Whatever is left once the body ends is the last line.
+        let mut line_number = 0;
+        let mut end_of_body = false;
+        // Errors that stop reading the body are only returned once the parsers and indexer have shut down, so
+        // nothing is still being written when the insert is rolled back
+        let mut failure = None;
+        'read: while !end_of_body {
+            match body.next().await {
+                Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    let err = format!("Error reading request body after line {}: {}", line_number, e);
+                    failure = Some(Error::QueryError(err));
+                    break;
+                }
+                None => end_of_body = true,
+            }
+            if progress.is_cancelled() || (options.on_error == OnError::Rollback && !err_rcv.is_empty()) {
+                break;
+            }
+
+            loop {
+                let decoded = if end_of_body {
+                    decoder.decode_eof(&mut buf)
+                } else {
+                    decoder.decode(&mut buf)
+                };
+                // Records are parsed in chunks, one goes off when it's full or when the reader waits on more of the body
+                if chunk.len() >= PARSE_CHUNK_SIZE || (matches!(decoded, Ok(None)) && !chunk.is_empty()) {
+                    if let Some(parser) = &parser {
+                        if let Err(e) = self.parse(parser, std::mem::take(&mut chunk), &doc_sender, &err_snd).await {
+                            failure = Some(e);
+                            break 'read;
+                        }
+                    }
+                }
+                match decoded {
+                    Ok(Some(l)) => {
+                        line_number += 1;
+                        let record = match records.as_mut() {
+                            None => Some((line_number, l.trim().to_string())).filter(|(_, l)| !l.is_empty()),
+                            Some(records) => records.push(line_number, l.trim_end_matches('\r')),
+                        };
+                        match (delimiter, record) {
+                            (_, None) => continue,
+                            (Some(delimiter), Some(record)) if parser.is_none() => {
+                                let columns = match split_record(&record.1, delimiter, options.quote) {
+                                    Ok(columns) => columns,
+                                    Err(e) => {
+                                        let err = format!("Invalid header on line {}: {}", record.0, e);
+                                        failure = Some(Error::QueryError(err));
+                                        break 'read;
+                                    }
+                                };
+                                let format = RecordFormat::Delimited {
+                                    columns: columns.into_iter().map(|c| c.trim().to_string()).collect(),
+                                    delimiter,
+                                    quote: options.quote,
+                                };
+                                parser = Some(new_parser(format));
+                            }
+                            (_, Some(record)) => {
+                                progress.add_read(1);
+                                chunk.push(record);
+                            }
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(LinesCodecError::MaxLineLengthExceeded) => {
+                        let err_txt = format!(
+                            "Line exceeded max length of {}, you can increase this with the max_line_length config option",
+                            max_line_length
+                        );
+                        failure = Some(Error::TantivyError(anyhow::Error::msg(err_txt)));
+                        break 'read;
+                    }
+                    Err(err) => {
+                        failure = Some(Error::TantivyError(anyhow::Error::msg("Error with codec.").context(err)));
+                        break 'read;
+                    }
+                }
+            }
+        }
+        match records.as_mut().and_then(DelimitedRecords::finish) {
+            Some(record) if failure.is_none() && parser.is_none() => {
+                let err = format!("Invalid header on line {}: Unterminated quoted column", record.0);
+                failure = Some(Error::QueryError(err));
+            }
+            Some(record) if failure.is_none() => {
+                progress.add_read(1);
+                chunk.push(record);
+            }
+            _ => {}
+        }
+        // The records read before the body ended or failed to read are still parsed, a failure in rollback mode or a
+        // cancel throws them away anyway
+        if let (Some(parser), false) = (&parser, chunk.is_empty()) {
+            if let Err(e) = self.parse(parser, chunk, &doc_sender, &err_snd).await {
+                failure = failure.or(Some(e));
+            }
+        }
+
+        // Every chunk handed to the parsers holds a sender of documents until it's parsed, so once the indexer is done
+        // so is the parsing
+        drop(doc_sender);
+        let indexed = indexer
+            .await
+            .unwrap_or_else(|e| Err(Error::TantivyError(anyhow::Error::msg("Indexing documents failed").context(e))));
+
+        let mut errors: Vec<BulkLineError> = err_rcv.drain().collect();
+        errors.sort_by_key(|e| e.line);
+        let parse_error = match errors.first() {
+            Some(err) if options.on_error == OnError::Rollback => {
+                let err_msg = anyhow::Error::msg(format!("Error parsing document on line {}: {}", err.line, err.message));
+                Some(Error::TantivyError(err_msg))
+            }
+            _ => None,
+        };
+        // Documents are counted as pending as they're written, so in skip mode the ones written before a failure
+        // are kept and committed like any other write. In rollback mode a failure throws all of them away.
+        let accepted = match failure.or(parse_error).map_or(indexed, Err) {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                if options.on_error == OnError::Rollback {
+                    self.rollback().await?;
+                }
+                return Err(err);
+            }
+        };
+        // Only an insert that rolls back on errors throws away what it wrote when cancelled, like it would on an error
+        if progress.is_cancelled() {
+            if options.on_error == OnError::Rollback {
+                info!("Bulk insert into {} was cancelled, rolling back", self.index);
+                self.rollback().await?;
+            } else {
+                info!("Bulk insert into {} was cancelled after writing {} documents", self.index, accepted);
+            }
+            return Err(Error::TaskCancelled);
+        }
+        Ok(BulkResponse {
+            accepted,
+            rejected: errors.len() as u64,
+            errors,
+        })
+    }
+}
+
+/// Write a request body out to an anonymous file in `dir`, which is gone once it's closed, and give back a body that
+/// reads it from there
+async fn spool_body(mut body: Body, dir: &Path) -> Result<Body, Error> {
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let mut file = tokio::fs::File::from_std(tempfile::tempfile_in(dir)?);
+    while let Some(chunk) = body.next().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await?;
+    file.rewind().await?;
+    Ok(Body::wrap_stream(ReaderStream::new(file)))
+}
+
+/// Insert newline delimited documents into an index. With `wait=false` the insert runs as a background task and
+/// the response only holds the task's id, which can be used to follow its progress or cancel it. That response is
+/// sent once the body has been written out under the data path, so the client can go away while the task reads it.
+#[allow(clippy::too_many_arguments)]
 pub async fn bulk_insert<C: Catalog>(
     catalog: Arc<C>,
-    watcher: Arc<AtomicBool>,
-    mut body: Body,
+    tasks: Arc<TaskRegistry>,
+    body: Body,
     index: &str,
-    num_threads: usize,
+    query: &str,
+    parsers: Arc<ParserPool>,
+    buffer_size: usize,
     max_line_length: usize,
 ) -> ResponseFuture {
-    if !catalog.exists(index) {
-        return not_found().await;
-    }
-    watcher.store(true, Ordering::SeqCst);
-    let index_handle = catalog.get_index(index).unwrap();
-    let writer = index_handle.get_writer();
-    let i = index_handle.get_index();
-    let schema = i.schema();
-
-    let (line_sender, line_recv) = unbounded::<String>();
-    let (doc_sender, doc_recv) = unbounded::<Document>();
-    let (err_snd, err_rcv) = unbounded();
-
-    info!("Spawning {} parsing threads...", num_threads);
-    let mut parsing_handles = Vec::with_capacity(num_threads);
-    for _ in 0..num_threads {
-        let schema = schema.clone();
-        let doc_sender = doc_sender.clone();
-        let line_recv = line_recv.clone();
-        let err_snd = err_snd.clone();
-        parsing_handles.push(tokio::spawn(parsing_documents(schema, doc_sender, line_recv, err_snd)));
-    }
-    info!("Spawned threads finished...");
-    let mut buf = BytesMut::new();
-    let mut decoder = if max_line_length > 0 {
-        LinesCodec::new_with_max_length(max_line_length)
+    let options = match serde_urlencoded::from_str::<BulkOptions>(query) {
+        Ok(options) => options,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, Error::QueryError(e.to_string()))),
+    };
+    let pipeline = match options.pipeline.as_deref().map(|name| catalog.get_pipeline(name)).transpose() {
+        Ok(pipeline) => pipeline,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let index_handle = match catalog.get_or_create_index(index).await {
+        Ok(handle) => handle,
+        Err(Error::UnknownIndex(_)) => return not_found().await,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let format = match (options.delimiter(), &options.columns) {
+        (None, _) => Some(RecordFormat::Json),
+        (Some(delimiter), Some(columns)) if !options.header => Some(RecordFormat::Delimited {
+            columns: columns.split(',').map(|c| c.trim().to_string()).collect(),
+            delimiter,
+            quote: options.quote,
+        }),
+        (Some(_), None) if !options.header => {
+            let err = Error::QueryError("columns must be given when the body has no header".into());
+            return Ok(error_response(StatusCode::BAD_REQUEST, err));
+        }
+        (Some(_), _) => None,
+    };
+    let body = if options.wait {
+        body
     } else {
-        LinesCodec::new()
+        match spool_body(body, Path::new(&catalog.base_path())).await {
+            Ok(body) => body,
+            Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+        }
     };
 
-    while let Some(Ok(line)) = body.next().await {
-        buf.extend_from_slice(&line);
+    let session = match options.on_error {
+        OnError::Rollback => index_handle.begin_exclusive_bulk(),
+        OnError::Skip => index_handle.begin_bulk(),
+    };
+    let session = match session {
+        Ok(session) => session,
+        Err(e) => return Ok(error_response(StatusCode::CONFLICT, e)),
+    };
+    // Commit whatever was written before the insert took the index, so rolling it back can't take those writes along
+    if options.on_error == OnError::Rollback && index_handle.get_commit_status().pending_docs > 0 {
+        if let Err(e) = index_handle.commit().await {
+            return Ok(error_response(StatusCode::BAD_REQUEST, e));
+        }
+    }
 
-        loop {
-            match decoder.decode_eof(&mut buf) {
-                Ok(Some(l)) if !l.is_empty() => {
-                    let l = l.trim();
-                    line_sender.send_async(l.into()).await.unwrap();
+    let wait = options.wait;
+    let progress = Arc::new(TaskProgress::default());
+    let ingest = BulkIngest {
+        index: index.into(),
+        _session: session,
+        schema: index_handle.get_index().schema(),
+        handle: index_handle,
+        options,
+        pipeline,
+        format,
+        parsers,
+        buffer_size,
+        max_line_length,
+        progress: Arc::clone(&progress),
+    };
+
+    if !wait {
+        let task = tasks.spawn("bulk", progress, async move { ingest.run(body).await.map(|_| ()) });
+        let mut resp = with_body(TaskCreated { task });
+        *resp.status_mut() = StatusCode::ACCEPTED;
+        return Ok(resp);
+    }
+    match ingest.run(body).await {
+        Ok(response) => {
+            let mut resp = with_body(response);
+            *resp.status_mut() = StatusCode::CREATED;
+            Ok(resp)
+        }
+        Err(err) => Ok(error_response(StatusCode::BAD_REQUEST, err)),
+    }
+}
+
+/// Documents written by id earlier in a bulk actions request, keyed by index and id, so later updates in the same
+/// request build on them rather than on the last commit. `None` marks a document the request deleted.
+type Written = HashMap<(String, String), Option<serde_json::Value>>;
+
+/// The most documents a bulk actions request keeps for later updates to build on
+const MAX_WRITTEN: usize = 10_000;
+
+/// Keep a document written by a bulk actions request. Once `MAX_WRITTEN` are kept only those already kept are
+/// changed, an update of any other document the request wrote fails since its write is still waiting on a commit.
+fn remember(written: &mut Written, key: (String, String), document: Option<serde_json::Value>) {
+    if written.len() < MAX_WRITTEN || written.contains_key(&key) {
+        written.insert(key, document);
+    }
+}
+
+async fn apply_action<C: Catalog>(
+    catalog: &Arc<C>,
+    action: &BulkAction,
+    source: Option<&str>,
+    pipeline: Option<&Pipeline>,
+    written: &mut Written,
+) -> Result<StatusCode, Error> {
+    let target = action.target();
+    let mut document = source.map(serde_json::from_str::<serde_json::Value>).transpose()?;
+    // Pipelines shape whole documents, so they aren't run over the partial ones given to update
+    if let (Some(pipeline), Some(doc), BulkAction::Index(_) | BulkAction::Upsert(_)) = (pipeline, document.as_mut(), action) {
+        pipeline.run(doc)?;
+    }
+    let document = || {
+        document
+            .clone()
+            .ok_or_else(|| Error::QueryError(format!("{} needs a document", action.name())))
+    };
+    let id = || {
+        target
+            .id
+            .as_deref()
+            .ok_or_else(|| Error::QueryError(format!("{} needs an _id", action.name())))
+    };
+    match action {
+        BulkAction::Index(_) => {
+            if target.id.is_some() {
+                return Err(Error::QueryError(
+                    "index does not take an _id, use upsert to write a document by id".into(),
+                ));
+            }
+            let handle = catalog.get_or_create_index(&target.index).await?;
+            let document = document()?;
+            // A document carrying its primary key replaces the one with that id, so it can be updated later on
+            let key = handle.get_config().primary_key.and_then(|key| match document.get(&key)? {
+                serde_json::Value::String(s) => Some(s.clone()),
+                serde_json::Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            });
+            handle.add_document(AddDocument::new(document.clone(), None)).await?;
+            if let Some(id) = key {
+                remember(written, (handle.get_name(), id), Some(document));
+            }
+            Ok(StatusCode::CREATED)
+        }
+        BulkAction::Upsert(_) => {
+            let handle = catalog.get_or_create_index(&target.index).await?;
+            let document = document()?;
+            handle.upsert_document(id()?, AddDocument::new(document.clone(), None)).await?;
+            remember(written, (handle.get_name(), id()?.into()), Some(document));
+            Ok(StatusCode::CREATED)
+        }
+        BulkAction::Update(_) => {
+            let handle = catalog.get_index(&target.index)?;
+            let key = (handle.get_name(), id()?.to_string());
+            let updated = match written.get(&key) {
+                Some(Some(earlier)) => {
+                    let mut updated = earlier.clone();
+                    merge_patch(&mut updated, &document()?);
+                    handle.upsert_document(&key.1, AddDocument::new(updated.clone(), None)).await?;
+                    updated
                 }
-                Ok(None) | Ok(Some(_)) => break,
-                Err(LinesCodecError::MaxLineLengthExceeded) => {
-                    let err_txt = format!(
-                        "Line exceeded max length of {}, you can increase this with the max_line_length config option",
-                        max_line_length
-                    );
-                    let err_msg = anyhow::Error::msg(err_txt);
-                    return Ok(error_response(StatusCode::BAD_REQUEST, Error::TantivyError(err_msg)));
+                Some(None) => return Err(Error::UnknownDocument(key.1)),
+                None => handle.update_document(&key.1, AddDocument::new(document()?, None)).await?,
+            };
+            remember(written, key, Some(updated));
+            Ok(StatusCode::OK)
+        }
+        BulkAction::Delete(_) => {
+            let handle = catalog.get_index(&target.index)?;
+            match &target.id {
+                Some(id) => {
+                    handle.delete_document(id, None).await?;
+                    remember(written, (handle.get_name(), id.clone()), None);
                 }
-                Err(err) => {
-                    let err_msg = anyhow::Error::msg("Error with codec.").context(err);
-                    return Ok(error_response(StatusCode::BAD_REQUEST, Error::TantivyError(err_msg)));
+                None if !target.terms.is_empty() => {
+                    let delete = DeleteDoc {
+                        options: None,
+                        terms: target.terms.clone(),
+                    };
+                    handle.delete_term(delete).await?;
+                    // There's no telling which of the documents written earlier matched the terms
+                    written.retain(|(index, _), _| *index != handle.get_name());
+                }
+                None => return Err(Error::QueryError("delete needs an _id or terms".into())),
+            };
+            Ok(StatusCode::OK)
+        }
+    }
+}
+
+/// Reads the non-blank lines of a request body as it arrives, so a body is never held in memory all at once
+struct BodyLines {
+    body: Body,
+    buf: BytesMut,
+    decoder: LinesCodec,
+    line_number: usize,
+    end_of_body: bool,
+}
+
+impl BodyLines {
+    fn new(body: Body) -> Self {
+        Self {
+            body,
+            buf: BytesMut::new(),
+            decoder: LinesCodec::new(),
+            line_number: 0,
+            end_of_body: false,
+        }
+    }
+
+    /// The next non-blank line and its line number, or `None` once the body is done
+    async fn next(&mut self) -> Option<Result<(usize, String), Error>> {
+        loop {
+            let decoded = if self.end_of_body {
+                self.decoder.decode_eof(&mut self.buf)
+            } else {
+                self.decoder.decode(&mut self.buf)
+            };
+            match decoded {
+                Ok(Some(line)) => {
+                    self.line_number += 1;
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        return Some(Ok((self.line_number, line.to_string())));
+                    }
+                }
+                Ok(None) if self.end_of_body => return None,
+                Ok(None) => match self.body.next().await {
+                    Some(Ok(chunk)) => self.buf.extend_from_slice(&chunk),
+                    Some(Err(e)) => {
+                        let err = format!("Error reading request body after line {}: {}", self.line_number, e);
+                        return Some(Err(Error::QueryError(err)));
+                    }
+                    None => self.end_of_body = true,
+                },
+                Err(e) => {
+                    let err = format!("Invalid line {}: {}", self.line_number + 1, e);
+                    return Some(Err(Error::QueryError(err)));
                 }
             }
         }
     }
+}
 
-    futures::future::join_all(parsing_handles).await;
-    if !err_rcv.is_empty() {
-        let mut iw = writer.lock().await;
-        iw.rollback()
-            .unwrap_or_else(|e| panic!("Error rolling back index: {}, this should be reported as a bug. {}", index, e));
-        match err_rcv.recv_async().await {
-            Ok(err) => return Ok(error_response(StatusCode::BAD_REQUEST, err)),
-            Err(err) => panic!("Panic receiving error: {:?}", err),
+/// Run a request of action lines, each followed by a document line when the action needs one, against any
+/// number of indexes. Actions run in order and each gets its own result, a malformed action line fails the
+/// whole request since the lines after it can't be paired up. An update sees the documents written by id
+/// earlier in the same request, even though they aren't committed yet, for up to `MAX_WRITTEN` documents.
+pub async fn bulk_actions<C: Catalog>(catalog: Arc<C>, body: Body, query: &str) -> ResponseFuture {
+    let options = match serde_urlencoded::from_str::<IndexOptions>(query) {
+        Ok(options) => options,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, Error::QueryError(e.to_string()))),
+    };
+    let pipeline = match serde_urlencoded::from_str::<WriteOptions>(query)
+        .map_err(|e| Error::QueryError(e.to_string()))
+        .and_then(|o| o.pipeline.map(|name| catalog.get_pipeline(&name)).transpose())
+    {
+        Ok(pipeline) => pipeline,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+
+    let mut lines = BodyLines::new(body);
+    let mut response = BulkActionsResponse::default();
+    let mut written = Written::new();
+    let mut indexes = BTreeSet::new();
+    while let Some(line) = lines.next().await {
+        let (line_number, line) = match line {
+            Ok(line) => line,
+            Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+        };
+        let action = match serde_json::from_str::<BulkAction>(&line) {
+            Ok(action) => action,
+            Err(e) => {
+                let err = Error::QueryError(format!("Invalid action on line {}: {}", line_number, e));
+                return Ok(error_response(StatusCode::BAD_REQUEST, err));
+            }
+        };
+        let source = if action.has_source() {
+            match lines.next().await {
+                Some(Ok((_, source))) => Some(source),
+                Some(Err(e)) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+                None => {
+                    let err = Error::QueryError(format!("Action on line {} is missing its document", line_number));
+                    return Ok(error_response(StatusCode::BAD_REQUEST, err));
+                }
+            }
+        } else {
+            None
+        };
+
+        let target = action.target();
+        let mut item = BulkItemResult {
+            action: action.name().into(),
+            index: target.index.clone(),
+            id: target.id.clone(),
+            status: StatusCode::OK.as_u16(),
+            error: None,
+        };
+        match apply_action(&catalog, &action, source.as_deref(), pipeline.as_ref(), &mut written).await {
+            Ok(status) => {
+                item.status = status.as_u16();
+                indexes.insert(target.index.clone());
+            }
+            Err(e) => {
+                item.status = match e {
+                    Error::UnknownIndex(_) | Error::UnknownDocument(_) => StatusCode::NOT_FOUND.as_u16(),
+                    Error::DocumentPending(_) => StatusCode::CONFLICT.as_u16(),
+                    _ => StatusCode::BAD_REQUEST.as_u16(),
+                };
+                item.error = Some(e.to_string());
+                response.errors = true;
+            }
         }
+        response.items.push(item);
     }
 
-    match index_documents(writer, doc_recv, Arc::clone(&watcher)).await {
-        Ok(_) => Ok(empty_with_code(StatusCode::CREATED)),
-        Err(err) => Ok(error_response(StatusCode::BAD_REQUEST, err)),
+    if options.commit {
+        for index in indexes {
+            if let Ok(handle) = catalog.get_index(&index) {
+                if let Err(e) = handle.commit().await {
+                    return Ok(error_response(StatusCode::BAD_REQUEST, e));
+                }
+            }
+        }
     }
+    Ok(with_body(response))
 }
 
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
-    use crate::commit::tests::read_body;
+    use crate::commit::commit_due_indexes;
+    use crate::commit::tests::{read_body, wait_json};
     use crate::handlers::all_docs;
     use crate::handlers::summary::flush;
-    use crate::index::create_test_catalog;
+    use crate::index::{create_test_catalog, create_test_catalog_at};
+    use crate::tasks::tests::wait_for_task;
     use crate::SearchResults;
 
     use super::*;
@@ -149,20 +891,27 @@ mod tests {
     #[tokio::test]
     async fn test_bulk_index() -> Result<(), Box<dyn std::error::Error>> {
         let server = create_test_catalog("test_index_bulk");
-        let lock = Arc::new(AtomicBool::new(false));
 
         let body = r#"{"test_text": "asdf1234", "test_i64": 123, "test_u64": 321, "test_unindex": "asdf", "test_facet": "/cat/cat4"}
         {"test_text": "asdf5678", "test_i64": 456, "test_u64": 678, "test_unindex": "asdf", "test_facet": "/cat/cat4"}
         {"test_text": "asdf9012", "test_i64": -12, "test_u64": 901, "test_unindex": "asdf", "test_facet": "/cat/cat4"}"#;
 
-        let index_docs = bulk_insert(Arc::clone(&server), lock, Body::from(body), "test_index_bulk", 2, 2048).await?;
+        let index_docs = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(body),
+            "test_index_bulk",
+            "",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
         assert_eq!(index_docs.status(), StatusCode::CREATED);
 
-        let f = flush(Arc::clone(&server), "test_index_bulk").await?;
-
-        assert_eq!(f.status(), StatusCode::OK);
-
-        std::thread::sleep(Duration::from_secs(1));
+        // One pass of the commit watcher, a commit reloads the reader before it returns so the documents are searchable
+        commit_due_indexes(&*server, f32::MIN_POSITIVE).await;
+        assert!(server.get_index("test_index_bulk")?.get_commit_status().last_commit.is_some());
         let check_docs = all_docs(Arc::clone(&server), "test_index_bulk").await?;
         let body: String = read_body(check_docs).await?;
         let docs: SearchResults = serde_json::from_slice(body.as_bytes())?;
@@ -174,17 +923,469 @@ mod tests {
     #[tokio::test]
     async fn test_errors() -> Result<(), Box<dyn std::error::Error>> {
         let server = create_test_catalog("test_index");
-        let lock = Arc::new(AtomicBool::new(false));
+        let pending = r#"{"document": {"test_text": "Pending", "test_i64": 1, "test_u64": 1}}"#;
+        crate::handlers::add_document(Arc::clone(&server), Body::from(pending), "test_index", "").await?;
 
         let body: &str = r#"{"test_text": "asdf1234", "test_i64": 123, "test_u64": 321, "test_unindex": "asdf", "test_facet": "/cat/cat4"}
         {"test_text": "asdf5678", "test_i64": 456, "test_u64": 678, "test_unindex": "asdf", "test_facet": "/cat/cat4"}
         {"test_text": "asdf9012", "test_i64": -12, "test_u64": -9, "test_unindex": "asdf", "test_facet": "/cat/cat4"}"#;
 
-        let index_docs = bulk_insert(Arc::clone(&server), lock, Body::from(body), "test_index", 2, 2048).await?;
+        let index_docs = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(body),
+            "test_index",
+            "on_error=rollback",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
         assert_eq!(index_docs.status(), StatusCode::BAD_REQUEST);
 
         let body = read_body(index_docs).await?;
         println!("{}", body);
+        // Nothing the rolled back insert wrote is left waiting on a commit
+        assert_eq!(server.get_index("test_index")?.get_commit_status().pending_docs, 0);
+
+        // The rollback only throws away the insert, the document written before it was committed first
+        flush(Arc::clone(&server), "test_index").await?;
+        let docs: SearchResults = wait_json(all_docs(Arc::clone(&server), "test_index").await?).await;
+        assert_eq!(docs.hits, 6);
+
+        // Only a rolling back insert needs the index to itself, one that skips errors runs alongside other writes
+        let _session = server.get_index("test_index")?.begin_bulk()?;
+        let busy = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(""),
+            "test_index",
+            "on_error=rollback",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        assert_eq!(busy.status(), StatusCode::CONFLICT);
+        let shared = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(""),
+            "test_index",
+            "",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        assert_eq!(shared.status(), StatusCode::CREATED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skip_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let server = create_test_catalog("test_index");
+
+        let body: &str = r#"{"test_text": "asdf1234", "test_i64": 123, "test_u64": 321, "test_unindex": "asdf", "test_facet": "/cat/cat4"}
+        {"test_text": "asdf5678", "test_i64": 456, "test_u64": -9, "test_unindex": "asdf", "test_facet": "/cat/cat4"}
+
+        {"test_text": "asdf9012", "test_i64": -12, "test_u64": 901, "test_unindex": "asdf", "test_facet": "/cat/cat4"}
+        {"test_text": "asdf9012", "#;
+
+        let index_docs = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(body),
+            "test_index",
+            "on_error=skip",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        assert_eq!(index_docs.status(), StatusCode::CREATED);
+        let response: BulkResponse = wait_json(index_docs).await;
+        assert_eq!(response.accepted, 2);
+        assert_eq!(response.rejected, 2);
+        let lines: Vec<usize> = response.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 5]);
+
+        let bad_option = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(""),
+            "test_index",
+            "on_error=maybe",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        assert_eq!(bad_option.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_gzip() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        use async_compression::tokio::bufread::GzipEncoder;
+        use tokio::io::AsyncReadExt;
+
+        let server = create_test_catalog("test_index");
+        let body = r#"{"test_text": "Gzipped", "test_i64": 1, "test_u64": 2}
+        {"test_text": "Gzipped", "test_i64": 3, "test_u64": 4}"#;
+        let mut compressed = Vec::new();
+        GzipEncoder::new(body.as_bytes()).read_to_end(&mut compressed).await?;
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::CONTENT_ENCODING, http::HeaderValue::from_static("gzip"));
+        let body = crate::utils::decode_body(&headers, Body::from(compressed))?;
+
+        let resp = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            body,
+            "test_index",
+            "",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let response: BulkResponse = wait_json(resp).await;
+        assert_eq!(response.accepted, 2);
+
+        let corrupt = crate::utils::decode_body(&headers, Body::from("not gzip"))?;
+        let resp = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            corrupt,
+            "test_index",
+            "",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_slow_split_body() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let server = create_test_catalog("test_index");
+        let chunks = vec![
+            r#"{"test_text": "Split acr"#,
+            r#"oss chunks", "test_i64": 1, "test_u64": 2}"#,
+            "\n{\"test_text\": \"Last line\", \"test_i64\": 3, \"test_u64\": 4}",
+        ];
+        // Pauses between chunks longer than any timeout a reader might give up after
+        let body = futures::stream::iter(chunks).then(|chunk| async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok::<_, std::io::Error>(chunk)
+        });
+        let resp = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::wrap_stream(body),
+            "test_index",
+            "",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let response: BulkResponse = wait_json(resp).await;
+        assert_eq!(response.accepted, 2);
+        assert_eq!(response.rejected, 0);
+        assert!(!server.get_index("test_index")?.in_bulk());
+
+        // A line that can't be read fails the insert, but in skip mode the documents before it are kept
+        let too_long = format!("{{\"test_text\": \"Short\"}}\n{{\"test_text\": \"{}\"}}", "a".repeat(100));
+        let handle = server.get_index("test_index")?;
+        let pending = handle.get_commit_status().pending_docs;
+        let resp = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(too_long.clone()),
+            "test_index",
+            "",
+            Arc::new(ParserPool::new(2)),
+            2,
+            32,
+        )
+        .await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(!handle.in_bulk());
+        assert_eq!(handle.get_commit_status().pending_docs, pending + 1);
+
+        let resp = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(too_long),
+            "test_index",
+            "on_error=rollback",
+            Arc::new(ParserPool::new(2)),
+            2,
+            32,
+        )
+        .await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(handle.get_commit_status().pending_docs, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_shared_parsers() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let server = create_test_catalog("test_index");
+        let parsers = Arc::new(ParserPool::new(1));
+        let body = || {
+            let lines: Vec<String> = (0..600)
+                .map(|i| format!("{{\"test_text\": \"Shared\", \"test_i64\": {}, \"test_u64\": {}}}", i, i))
+                .collect();
+            Body::from(lines.join("\n"))
+        };
+        // Inserts running at the same time take turns on the one parser thread
+        let insert = || {
+            bulk_insert(
+                Arc::clone(&server),
+                Arc::new(TaskRegistry::default()),
+                body(),
+                "test_index",
+                "",
+                Arc::clone(&parsers),
+                2,
+                2048,
+            )
+        };
+        let responses = futures::future::join_all((0..3).map(|_| insert())).await;
+        for resp in responses {
+            let resp = resp?;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+            let response: BulkResponse = wait_json(resp).await;
+            assert_eq!((response.accepted, response.rejected), (600, 0));
+        }
+        assert_eq!(server.get_index("test_index")?.get_commit_status().pending_docs, 1800);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_no_wait() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let server = create_test_catalog("test_index");
+        let tasks = Arc::new(TaskRegistry::default());
+        let (mut sender, body) = Body::channel();
+        let upload = tokio::spawn(async move {
+            sender
+                .send_data("{\"test_text\": \"Later\", \"test_i64\": 1, \"test_u64\": 2}\n".into())
+                .await?;
+            sender
+                .send_data("{\"test_text\": \"Later\", \"test_i64\": 3, \"test_u64\": -4}".into())
+                .await
+        });
+        let resp = bulk_insert(
+            Arc::clone(&server),
+            Arc::clone(&tasks),
+            body,
+            "test_index",
+            "wait=false",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        // The task is only created once the whole body has been received, so the client doesn't have to stay around
+        assert!(upload.is_finished());
+        upload.await??;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let created: TaskCreated = wait_json(resp).await;
+
+        let status = wait_for_task(&tasks, created.task).await;
+        assert_eq!(status.state, toshi_types::TaskState::Completed);
+        assert_eq!(status.read, 2);
+        assert_eq!(status.written, 1);
+        assert_eq!(status.errors, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_record() {
+        let columns = split_record(
+            r#"plain,"quoted, with comma","say ""hi""",,"multi
+line""#,
+            ',',
+            '"',
+        )
+        .unwrap();
+        assert_eq!(columns, vec!["plain", "quoted, with comma", r#"say "hi""#, "", "multi\nline"]);
+        assert_eq!(split_record("a\tb", '\t', '"').unwrap(), vec!["a", "b"]);
+        assert!(split_record(r#""open,"#, ',', '"').is_err());
+
+        // A quote partway through a column is part of it, so it doesn't hold the record open onto the next line
+        assert_eq!(split_record(r#"6" tall,b"#, ',', '"').unwrap(), vec![r#"6" tall"#, "b"]);
+        let mut records = DelimitedRecords::new(',', '"');
+        assert_eq!(records.push(1, r#"6" tall,b"#), Some((1, r#"6" tall,b"#.to_string())));
+        assert_eq!(records.push(2, r#""multi,"#), None);
+        assert_eq!(records.push(3, r#"line""#), Some((2, "\"multi,\nline\"".to_string())));
+        assert_eq!(records.finish(), None);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_csv() -> Result<(), Box<dyn std::error::Error>> {
+        let server = create_test_catalog("test_index");
+        let body = "test_text,test_i64,test_u64,unknown_column,test_facet\r
+\"Csv, Document\",1,2,ignored,/cat/csv\r
+\"Multi
+line\",-3,4,,/cat/csv
+Bad,1,-4,,/cat/csv
+";
+        let resp = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(body),
+            "test_index",
+            "format=csv&on_error=skip",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let response: BulkResponse = wait_json(resp).await;
+        assert_eq!(response.accepted, 2);
+        assert_eq!(response.rejected, 1);
+        assert_eq!(response.errors[0].line, 5);
+
+        let body = "Tsv Document\t5\t6\n";
+        let resp = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(body),
+            "test_index",
+            "format=tsv&header=false&columns=test_text,test_i64,test_u64",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        let response: BulkResponse = wait_json(resp).await;
+        assert_eq!(response.accepted, 1);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_bulk_pipeline() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dir = tempfile::tempdir()?;
+        let server = create_test_catalog_at("test_index", dir.path());
+        let pipeline = r#"{"processors": [
+            {"rename": {"field": "text", "target_field": "test_text"}},
+            {"split": {"field": "test_text", "separator": "|"}}
+        ]}"#;
+        server.put_pipeline("split_text", serde_json::from_str(pipeline)?)?;
+
+        let body = "text,test_i64\nfirst|second,1\n";
+        let resp = bulk_insert(
+            Arc::clone(&server),
+            Arc::new(TaskRegistry::default()),
+            Body::from(body),
+            "test_index",
+            "format=csv&pipeline=split_text",
+            Arc::new(ParserPool::new(2)),
+            2,
+            2048,
+        )
+        .await?;
+        let response: BulkResponse = wait_json(resp).await;
+        assert_eq!(response.accepted, 1);
+
+        let body = r#"{"index": {"_index": "test_index"}}
+        {"text": "third|fourth", "test_i64": 2}"#;
+        let resp = bulk_actions(Arc::clone(&server), Body::from(body), "commit=true&pipeline=split_text").await?;
+        let response: BulkActionsResponse = wait_json(resp).await;
+        assert!(!response.errors);
+
+        let resp = bulk_actions(Arc::clone(&server), Body::from(body), "pipeline=missing").await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_actions() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let server = create_test_catalog("test_index");
+        let body = r#"{"index": {"_index": "test_index"}}
+        {"test_text": "Bulk Action", "test_i64": 1, "test_u64": 1}
+        {"delete": {"_index": "test_index", "terms": {"test_text": "dockument"}}}
+        {"update": {"_index": "test_index", "_id": "1"}}
+        {"test_text": "Not Updated"}
+        {"index": {"_index": "missing_index"}}
+        {"test_text": "Nowhere"}
+        {"index": {"_index": "test_index"}}
+        {"test_text": "Bad", "test_u64": -1}"#;
+
+        let resp = bulk_actions(Arc::clone(&server), Body::from(body), "commit=true").await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let response: BulkActionsResponse = wait_json(resp).await;
+        assert!(response.errors);
+        let statuses: Vec<u16> = response.items.iter().map(|i| i.status).collect();
+        assert_eq!(statuses, vec![201, 200, 400, 404, 400]);
+        assert_eq!(response.items[1].action, "delete");
+        assert!(response.items[2].error.is_some());
+
+        let check_docs = all_docs(Arc::clone(&server), "test_index").await?;
+        let docs: SearchResults = wait_json(check_docs).await;
+        assert_eq!(docs.hits, 5);
+
+        let resp = bulk_actions(Arc::clone(&server), Body::from(r#"{"explode": {}}"#), "").await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bulk_actions_see_earlier_writes() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dir = tempfile::tempdir()?;
+        let server = create_test_catalog_at("test_index", dir.path());
+        let create = r#"{ "primary_key": "id", "schema": [
+            { "name": "id", "type": "text", "options": { "indexing": { "record": "basic", "tokenizer": "raw" }, "stored": true } },
+            { "name": "body", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } },
+            { "name": "count", "type": "u64", "options": { "indexed": true, "stored": true } }
+         ]}"#;
+        crate::handlers::create_index(Arc::clone(&server), Body::from(create), "bulk_ids").await?;
+
+        let body = r#"{"upsert": {"_index": "bulk_ids", "_id": "a"}}
+        {"body": "first", "count": 1}
+        {"update": {"_index": "bulk_ids", "_id": "a"}}
+        {"count": 2}
+        {"index": {"_index": "bulk_ids"}}
+        {"id": "b", "body": "second", "count": 1}
+        {"update": {"_index": "bulk_ids", "_id": "b"}}
+        {"body": "changed"}
+        {"delete": {"_index": "bulk_ids", "_id": "b"}}
+        {"update": {"_index": "bulk_ids", "_id": "b"}}
+        {"body": "gone"}"#;
+        // Chunks that end partway through lines, as a body read off the wire would
+        let chunks: Vec<String> = body.as_bytes().chunks(7).map(|c| String::from_utf8_lossy(c).into_owned()).collect();
+        let body = futures::stream::iter(chunks).map(Ok::<_, std::io::Error>);
+        let resp = bulk_actions(Arc::clone(&server), Body::wrap_stream(body), "commit=true").await?;
+        let response: BulkActionsResponse = wait_json(resp).await;
+        let statuses: Vec<u16> = response.items.iter().map(|i| i.status).collect();
+        assert_eq!(statuses, vec![201, 200, 201, 200, 200, 404]);
+
+        let handle = server.get_index("bulk_ids")?;
+        let doc = handle.get_document("a").await?.expect("the upserted document");
+        let doc = serde_json::to_value(doc)?;
+        assert_eq!(doc["body"], serde_json::json!("first"));
+        assert_eq!(doc["count"], serde_json::json!(2));
+        assert!(handle.get_document("b").await?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_remember_limit() {
+        let key = |id: usize| ("bulk_ids".to_string(), id.to_string());
+        let mut written: Written = (0..MAX_WRITTEN).map(|id| (key(id), None)).collect();
+        remember(&mut written, key(MAX_WRITTEN), Some(serde_json::json!({"body": "new"})));
+        assert!(!written.contains_key(&key(MAX_WRITTEN)));
+        remember(&mut written, key(0), Some(serde_json::json!({"body": "kept"})));
+        assert_eq!(written[&key(0)], Some(serde_json::json!({"body": "kept"})));
+        assert_eq!(written.len(), MAX_WRITTEN);
+    }
 }
@@ -2,9 +2,12 @@ use hyper::body::to_bytes;
 use hyper::{Body, Response, StatusCode};
 
 use toshi_types::{Catalog, IndexHandle};
-use toshi_types::{DeleteDoc, Error, SchemaBody};
+use toshi_types::{
+    CreateIndex, DeleteByQuery, DeleteDoc, Error, GetDocument, ReindexRequest, TaskCreated, TaskProgress, UpdateByQuery, WriteOptions,
+};
 
 use crate::handlers::ResponseFuture;
+use crate::tasks::TaskRegistry;
 use crate::utils::{empty_with_code, error_response, with_body};
 use crate::AddDocument;
 use std::sync::Arc;
@@ -27,32 +30,178 @@ pub async fn delete_term<C: Catalog>(catalog: Arc<C>, body: Body, index: &str) -
     }
 }
 
+pub async fn delete_by_query<C: Catalog>(catalog: Arc<C>, body: Body, index: &str) -> ResponseFuture {
+    if !catalog.exists(index) {
+        return Ok(error_response(StatusCode::BAD_REQUEST, Error::UnknownIndex(index.to_string())));
+    }
+    let agg_body = to_bytes(body).await?;
+    match serde_json::from_slice::<DeleteByQuery>(&agg_body) {
+        Ok(request) => match catalog.get_index(index) {
+            Ok(c) => c
+                .delete_by_query(request)
+                .await
+                .map(with_body)
+                .or_else(|e| Ok(error_response(StatusCode::BAD_REQUEST, e))),
+            Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+        },
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e.into())),
+    }
+}
+
+pub async fn update_by_query<C: Catalog>(catalog: Arc<C>, tasks: Arc<TaskRegistry>, body: Body, index: &str) -> ResponseFuture {
+    let handle = match catalog.get_index(index) {
+        Ok(h) => h,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    if handle.get_config().primary_key.is_none() {
+        return Ok(error_response(StatusCode::BAD_REQUEST, Error::MissingPrimaryKey(handle.get_name())));
+    }
+    let agg_body = to_bytes(body).await?;
+    match serde_json::from_slice::<UpdateByQuery>(&agg_body) {
+        Ok(request) => {
+            let progress = Arc::new(TaskProgress::default());
+            let job_progress = Arc::clone(&progress);
+            let session = match handle.begin_bulk() {
+                Ok(session) => session,
+                Err(e) => return Ok(error_response(StatusCode::CONFLICT, e)),
+            };
+            let task = tasks.spawn("update_by_query", progress, async move {
+                let _session = session;
+                handle.update_by_query(request, job_progress).await
+            });
+            let mut resp = with_body(TaskCreated { task });
+            *resp.status_mut() = StatusCode::ACCEPTED;
+            Ok(resp)
+        }
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e.into())),
+    }
+}
+
+pub async fn reindex<C: Catalog>(catalog: Arc<C>, tasks: Arc<TaskRegistry>, body: Body) -> ResponseFuture {
+    let agg_body = to_bytes(body).await?;
+    let request = match serde_json::from_slice::<ReindexRequest>(&agg_body) {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e.into())),
+    };
+    if request.source == request.dest {
+        let err = Error::QueryError("The source and destination of a reindex must be different indexes".into());
+        return Ok(error_response(StatusCode::BAD_REQUEST, err));
+    }
+    let (source, dest) = match (catalog.get_index(&request.source), catalog.get_index(&request.dest)) {
+        (Ok(source), Ok(dest)) => (source, dest),
+        (Err(e), _) | (_, Err(e)) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let progress = Arc::new(TaskProgress::default());
+    let job_progress = Arc::clone(&progress);
+    let session = match dest.begin_bulk() {
+        Ok(session) => session,
+        Err(e) => return Ok(error_response(StatusCode::CONFLICT, e)),
+    };
+    let task = tasks.spawn("reindex", progress, async move {
+        let _session = session;
+        source.reindex(dest, request, job_progress).await
+    });
+    let mut resp = with_body(TaskCreated { task });
+    *resp.status_mut() = StatusCode::ACCEPTED;
+    Ok(resp)
+}
+
 pub async fn create_index<C: Catalog>(catalog: Arc<C>, body: Body, index: &str) -> ResponseFuture {
     if catalog.exists(index) {
         return Ok(error_response(StatusCode::BAD_REQUEST, Error::AlreadyExists(index.to_string())));
     }
     let req = to_bytes(body).await?;
-    match serde_json::from_slice::<SchemaBody>(&req) {
-        Ok(schema_body) => match catalog.add_index(index, schema_body.0).await {
-            Ok(_) => Ok(empty_with_code(StatusCode::CREATED)),
-            Err(e) => Ok(Response::from(e)),
-        },
+    match serde_json::from_slice::<CreateIndex>(&req) {
+        Ok(create) => {
+            let (schema, config) = create.into_parts();
+            match catalog.add_index_with_config(index, schema, config).await {
+                Ok(_) => Ok(empty_with_code(StatusCode::CREATED)),
+                Err(e @ Error::InvalidConfig(_)) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+                Err(e) => Ok(Response::from(e)),
+            }
+        }
         Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e.into())),
     }
 }
 
-pub async fn add_document<C: Catalog>(catalog: Arc<C>, body: Body, index: &str) -> ResponseFuture {
-    if !catalog.exists(index) {
-        return Ok(error_response(StatusCode::BAD_REQUEST, Error::UnknownIndex(index.to_string())));
+pub async fn drop_index<C: Catalog>(catalog: Arc<C>, index: &str) -> ResponseFuture {
+    match catalog.remove_index(index).await {
+        Ok(_) => Ok(empty_with_code(StatusCode::OK)),
+        Err(e @ Error::IndexInUse(_)) => Ok(error_response(StatusCode::CONFLICT, e)),
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    }
+}
+
+/// Run a document through the pipeline named in a write's query string, if there is one
+fn apply_pipeline<C: Catalog>(catalog: &C, query: &str, doc: &mut AddDocument) -> Result<(), Error> {
+    let options = serde_urlencoded::from_str::<WriteOptions>(query).map_err(|e| Error::QueryError(e.to_string()))?;
+    match options.pipeline {
+        Some(name) => catalog.get_pipeline(&name)?.run(&mut doc.document),
+        None => Ok(()),
+    }
+}
+
+pub async fn add_document<C: Catalog>(catalog: Arc<C>, body: Body, index: &str, query: &str) -> ResponseFuture {
+    let handle = match catalog.get_or_create_index(index).await {
+        Ok(h) => h,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let full_body = to_bytes(body).await?;
+    let doc = serde_json::from_slice::<AddDocument>(&full_body)
+        .map_err(Error::from)
+        .and_then(|mut doc| apply_pipeline(&*catalog, query, &mut doc).map(|_| doc));
+    match doc {
+        Ok(v) => handle
+            .add_document(v)
+            .await
+            .map(|_| empty_with_code(StatusCode::CREATED))
+            .or_else(|e| Ok(error_response(StatusCode::BAD_REQUEST, e))),
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    }
+}
+
+pub async fn get_document<C: Catalog>(catalog: Arc<C>, index: &str, id: &str) -> ResponseFuture {
+    let handle = match catalog.get_index(index) {
+        Ok(h) => h,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    match handle.get_document(id).await {
+        Ok(Some(document)) => Ok(with_body(GetDocument { id: id.into(), document })),
+        Ok(None) => Ok(error_response(StatusCode::NOT_FOUND, Error::UnknownDocument(id.into()))),
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
     }
+}
+
+pub async fn put_document<C: Catalog>(catalog: Arc<C>, body: Body, index: &str, id: &str, query: &str) -> ResponseFuture {
+    let handle = match catalog.get_or_create_index(index).await {
+        Ok(h) => h,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let full_body = to_bytes(body).await?;
+    let doc = serde_json::from_slice::<AddDocument>(&full_body)
+        .map_err(Error::from)
+        .and_then(|mut doc| apply_pipeline(&*catalog, query, &mut doc).map(|_| doc));
+    match doc {
+        Ok(v) => handle
+            .upsert_document(id, v)
+            .await
+            .map(|_| empty_with_code(StatusCode::CREATED))
+            .or_else(|e| Ok(error_response(StatusCode::BAD_REQUEST, e))),
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    }
+}
+
+pub async fn update_document<C: Catalog>(catalog: Arc<C>, body: Body, index: &str, id: &str) -> ResponseFuture {
+    let handle = match catalog.get_index(index) {
+        Ok(h) => h,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
     let full_body = to_bytes(body).await?;
     match serde_json::from_slice::<AddDocument>(&full_body) {
-        Ok(v) => match catalog.get_index(index) {
-            Ok(c) => c
-                .add_document(v)
-                .await
-                .map(|_| empty_with_code(StatusCode::CREATED))
-                .or_else(|e| Ok(error_response(StatusCode::BAD_REQUEST, e))),
+        Ok(patch) => match handle.update_document(id, patch).await {
+            Ok(_) => Ok(empty_with_code(StatusCode::OK)),
+            Err(e @ Error::UnknownDocument(_)) => Ok(error_response(StatusCode::NOT_FOUND, e)),
+            Err(e @ Error::DocumentPending(_)) => Ok(error_response(StatusCode::CONFLICT, e)),
             Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
         },
         Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e.into())),
@@ -70,10 +219,11 @@ mod tests {
     use toshi_types::IndexOptions;
 
     use crate::handlers::all_docs;
-    use crate::index::create_test_catalog;
+    use crate::index::{create_test_catalog, create_test_catalog_at, IndexCatalog};
 
     use super::*;
-    use crate::commit::tests::wait_json;
+    use crate::commit::tests::{wait_json, wait_until};
+    use crate::tasks::tests::wait_for_task;
 
     fn test_index() -> String {
         String::from("test_index")
@@ -97,6 +247,39 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_drop_index() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let shared_cat = create_test_catalog("test_index");
+        let schema = r#"[{ "name": "test_text", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } }]"#;
+        create_index(Arc::clone(&shared_cat), Body::from(schema), "drop_index").await?;
+        let q = r#"{"document": {"test_text": "Uncommitted"}}"#;
+        add_document(Arc::clone(&shared_cat), Body::from(q), "drop_index", "").await?;
+        assert!(std::path::Path::new("drop_index").exists());
+
+        let session = shared_cat.get_index("drop_index")?.begin_bulk()?;
+        let resp = drop_index(Arc::clone(&shared_cat), "drop_index").await?;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        assert!(shared_cat.exists("drop_index"));
+        drop(session);
+
+        // A request still holding the index keeps its directory around until it's done
+        let held = shared_cat.get_index("drop_index")?;
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            assert!(std::path::Path::new("drop_index").exists());
+            drop(held);
+        });
+        let resp = drop_index(Arc::clone(&shared_cat), "drop_index").await?;
+        release.await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!shared_cat.exists("drop_index"));
+        assert!(!std::path::Path::new("drop_index").exists());
+
+        let resp = drop_index(Arc::clone(&shared_cat), "drop_index").await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
     #[cfg(feature = "extra_tokenizers")]
     #[tokio::test]
     async fn test_create_index_extra_tokenizers() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -112,7 +295,7 @@ mod tests {
 
         let q = r#" {"options": {"commit": true }, "document": {"test_text": "南京长江大桥", "test_u64": 10, "test_i64": -10} }"#;
 
-        add_document(Arc::clone(&shared_cat), Body::from(q), "new_index_extra_tok").await?;
+        add_document(Arc::clone(&shared_cat), Body::from(q), "new_index_extra_tok", "").await?;
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
         let resp = all_docs(Arc::clone(&shared_cat), "new_index_extra_tok").await?;
         let b = wait_json::<crate::SearchResults>(resp).await;
@@ -123,11 +306,154 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_doc_by_id() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let shared_cat = create_test_catalog("test_index");
+        let create = r#"{ "primary_key": "id", "schema": [
+            { "name": "id", "type": "text", "options": { "indexing": { "record": "basic", "tokenizer": "raw" }, "stored": true } },
+            { "name": "body", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } }
+         ]}"#;
+        let resp = create_index(Arc::clone(&shared_cat), Body::from(create), "doc_id_index").await?;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        for body in &["first", "second"] {
+            let doc = format!(r#"{{"options": {{"commit": true}}, "document": {{"body": "{}"}} }}"#, body);
+            let resp = put_document(Arc::clone(&shared_cat), Body::from(doc), "doc_id_index", "a-1", "").await?;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+        let resp = get_document(Arc::clone(&shared_cat), "doc_id_index", "a-1").await?;
+        let doc: GetDocument<serde_json::Value> = wait_json(resp).await;
+        assert_eq!(doc.id, "a-1");
+        assert_eq!(doc.document["body"], serde_json::json!("second"));
+
+        let resp = crate::handlers::doc_count(Arc::clone(&shared_cat), Body::empty(), "doc_id_index").await?;
+        let count: toshi_types::DocCount = wait_json(resp).await;
+        assert_eq!(count.count, 1);
+
+        let resp = get_document(Arc::clone(&shared_cat), "doc_id_index", "missing").await?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = get_document(Arc::clone(&shared_cat), "test_index", "1").await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        remove_dir_all::remove_dir_all("doc_id_index"); // Try, but don't fail on this.
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_doc_update() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let shared_cat = create_test_catalog("test_index");
+        let create = r#"{ "primary_key": "id", "schema": [
+            { "name": "id", "type": "u64", "options": { "indexed": true, "stored": true } },
+            { "name": "title", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } },
+            { "name": "rating", "type": "u64", "options": { "indexed": true, "stored": true } },
+            { "name": "body", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": false } }
+         ]}"#;
+        create_index(Arc::clone(&shared_cat), Body::from(create), "doc_update_index").await?;
+        let doc = r#"{"options": {"commit": true}, "document": {"title": "Old Title", "rating": 3, "body": "words"} }"#;
+        put_document(Arc::clone(&shared_cat), Body::from(doc), "doc_update_index", "7", "").await?;
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+        let patch = r#"{"options": {"commit": true}, "document": {"title": "New Title"} }"#;
+        let resp = update_document(Arc::clone(&shared_cat), Body::from(patch), "doc_update_index", "7").await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let patch = r#"{"options": {"commit": true}, "document": {"title": "New Title", "body": "new words"} }"#;
+        let resp = update_document(Arc::clone(&shared_cat), Body::from(patch), "doc_update_index", "7").await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+        let resp = get_document(Arc::clone(&shared_cat), "doc_update_index", "7").await?;
+        let doc: GetDocument<serde_json::Value> = wait_json(resp).await;
+        assert_eq!(doc.document["title"], serde_json::json!("New Title"));
+        assert_eq!(doc.document["rating"], serde_json::json!(3));
+        assert_eq!(doc.document["id"], serde_json::json!(7));
+
+        let resp = update_document(Arc::clone(&shared_cat), Body::from(patch), "doc_update_index", "8").await?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // The stored document is behind an upsert that isn't committed yet, so it can't be patched
+        let doc = r#"{"document": {"title": "Pending Title", "rating": 4, "body": "words"} }"#;
+        put_document(Arc::clone(&shared_cat), Body::from(doc), "doc_update_index", "7", "").await?;
+        let resp = update_document(Arc::clone(&shared_cat), Body::from(patch), "doc_update_index", "7").await?;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        shared_cat.get_index("doc_update_index")?.commit().await?;
+        let resp = update_document(Arc::clone(&shared_cat), Body::from(patch), "doc_update_index", "7").await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        remove_dir_all::remove_dir_all("doc_update_index"); // Try, but don't fail on this.
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bad_primary_key() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let shared_cat = create_test_catalog("test_index");
+        let create = r#"{ "primary_key": "body", "schema": [
+            { "name": "body", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } }
+         ]}"#;
+        let resp = create_index(Arc::clone(&shared_cat), Body::from(create), "bad_key_index").await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(!shared_cat.exists("bad_key_index"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_doc_with_pipeline() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dir = tempfile::tempdir()?;
+        let shared_cat = create_test_catalog_at("test_index", dir.path());
+        let pipeline = r#"{"processors": [
+            {"rename": {"field": "text", "target_field": "test_text"}},
+            {"convert": {"field": "test_u64", "to": "integer"}},
+            {"set_default": {"field": "test_i64", "value": 7}}
+        ]}"#;
+        shared_cat.put_pipeline("clean", serde_json::from_str(pipeline)?)?;
+
+        let q = r#"{"options": {"commit": true}, "document": {"text": "Piped", "test_u64": "20"}}"#;
+        let resp = add_document(Arc::clone(&shared_cat), Body::from(q), &test_index(), "pipeline=clean").await?;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = add_document(Arc::clone(&shared_cat), Body::from(q), &test_index(), "pipeline=missing").await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        let resp = all_docs(Arc::clone(&shared_cat), &test_index()).await?;
+        let b = wait_json::<crate::SearchResults>(resp).await;
+        assert_eq!(b.hits, 6);
+        let piped = b
+            .get_docs()
+            .iter()
+            .find(|d| d.doc.0.get("test_text").map(|v| *v.value() == "Piped").unwrap_or(false));
+        assert!(piped.is_some());
+        assert!(dir.path().join(crate::index::PIPELINES_FILE).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_doc_create_from_template() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dir = tempfile::tempdir()?;
+        let shared_cat = create_test_catalog_at("test_index", dir.path());
+        let template = r#"{ "index_patterns": ["template-logs-*"], "schema": [
+            { "name": "message", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } }
+         ]}"#;
+        let template = serde_json::from_str(template)?;
+        shared_cat.put_template("logs", template)?;
+
+        let q = r#"{"options": {"commit": true}, "document": {"message": "Started"}}"#;
+        let resp = add_document(Arc::clone(&shared_cat), Body::from(q), "template-logs-2021", "").await?;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert!(shared_cat.exists("template-logs-2021"));
+
+        let resp = add_document(Arc::clone(&shared_cat), Body::from(q), "other-logs", "").await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(dir.path().join("template-logs-2021").exists());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_doc_create() {
         let shared_cat = create_test_catalog("test_index");
         let q = r#" {"options": {"commit": true }, "document": {"test_text": "Babbaboo!", "test_u64": 10, "test_i64": -10} }"#;
-        let req = add_document(Arc::clone(&shared_cat), Body::from(q), &test_index()).await;
+        let req = add_document(Arc::clone(&shared_cat), Body::from(q), &test_index(), "").await;
         assert!(req.is_ok());
     }
 
@@ -146,6 +472,155 @@ mod tests {
         assert!(del.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_doc_delete_affected() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let shared_cat = create_test_catalog("test_index");
+        let body = r#"{"terms": {"test_text": "document"}}"#;
+        let resp = delete_term(Arc::clone(&shared_cat), Body::from(body), &test_index()).await?;
+        let affected: toshi_types::DocsAffected = wait_json(resp).await;
+        assert_eq!(affected.docs_affected, 3);
+        assert!(affected.pending);
+
+        let deleted = shared_cat.get_index(&test_index())?.get_deleted_docs();
+        assert_eq!(deleted.total, 3);
+        assert_eq!(deleted.in_segments, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete_by_query() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let shared_cat = create_test_catalog("test_index");
+        let body = r#"{"options": {"commit": true}, "query": {"range": {"test_u64": {"gte": 12}}}}"#;
+        let resp = delete_by_query(Arc::clone(&shared_cat), Body::from(body), &test_index()).await?;
+        let affected: toshi_types::DocsAffected = wait_json(resp).await;
+        assert_eq!(affected.docs_affected, 3);
+        assert!(!affected.pending);
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        let resp = all_docs(Arc::clone(&shared_cat), &test_index()).await?;
+        let b = wait_json::<crate::SearchResults>(resp).await;
+        assert_eq!(b.hits, 2);
+
+        let body = r#"{"query": {"range": {"test_unindex": {"gte": 12}}}}"#;
+        let resp = delete_by_query(Arc::clone(&shared_cat), Body::from(body), &test_index()).await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    /// A catalog with an index keyed by `id` holding five committed documents tagged "old", ranked 1 to 5
+    async fn create_keyed_catalog(dir: &std::path::Path) -> Result<Arc<IndexCatalog>, Box<dyn std::error::Error + Send + Sync>> {
+        let shared_cat = create_test_catalog_at("test_index", dir);
+        let create = r#"{ "primary_key": "id", "schema": [
+            { "name": "id", "type": "u64", "options": { "indexed": true, "stored": true } },
+            { "name": "tag", "type": "text", "options": { "indexing": { "record": "basic", "tokenizer": "raw" }, "stored": true } },
+            { "name": "rank", "type": "u64", "options": { "indexed": true, "stored": true } }
+         ]}"#;
+        create_index(Arc::clone(&shared_cat), Body::from(create), "keyed").await?;
+        for id in 1..=5 {
+            let doc = format!(r#"{{"document": {{"tag": "old", "rank": {}}}}}"#, id);
+            put_document(Arc::clone(&shared_cat), Body::from(doc), "keyed", &id.to_string(), "").await?;
+        }
+        shared_cat.get_index("keyed")?.commit().await?;
+        Ok(shared_cat)
+    }
+
+    #[tokio::test]
+    async fn test_update_by_query() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dir = tempfile::tempdir()?;
+        let shared_cat = create_keyed_catalog(dir.path()).await?;
+        let tasks = Arc::new(TaskRegistry::default());
+        let body = r#"{"options": {"commit": true}, "batch_size": 2, "query": {"range": {"rank": {"gte": 3}}}, "set": {"tag": "new"}}"#;
+        let resp = update_by_query(Arc::clone(&shared_cat), Arc::clone(&tasks), Body::from(body), "keyed").await?;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let created: TaskCreated = wait_json(resp).await;
+
+        let status = wait_for_task(&tasks, created.task).await;
+        assert_eq!(status.state, toshi_types::TaskState::Completed);
+        assert_eq!(status.total, 3);
+        assert_eq!(status.written, 3);
+
+        let resp = all_docs(Arc::clone(&shared_cat), "keyed").await?;
+        let b = wait_json::<crate::SearchResults>(resp).await;
+        let updated = b
+            .get_docs()
+            .iter()
+            .filter(|d| d.doc.0.get("tag").map(|v| *v.value() == "new").unwrap_or(false))
+            .count();
+        assert_eq!((b.hits, updated), (5, 3));
+
+        // A cancelled update stops before its next batch and says so, rather than passing as done
+        let progress = Arc::new(TaskProgress::default());
+        progress.cancel();
+        let request: UpdateByQuery = serde_json::from_str(r#"{"query": {"range": {"rank": {"gte": 0}}}, "set": {"tag": "cancelled"}}"#)?;
+        let cancelled = shared_cat.get_index("keyed")?.update_by_query(request, Arc::clone(&progress)).await;
+        assert!(matches!(cancelled, Err(Error::TaskCancelled)));
+        assert_eq!(
+            progress
+                .status(0, "update_by_query", toshi_types::TaskState::Cancelled, None)
+                .written,
+            0
+        );
+
+        // Without a primary key the old documents can't be replaced
+        let body = r#"{"query": {"range": {"test_u64": {"gte": 13}}}, "set": {"test_unindex": "updated"}}"#;
+        let resp = update_by_query(Arc::clone(&shared_cat), Arc::clone(&tasks), Body::from(body), &test_index()).await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_by_query_bad_value() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dir = tempfile::tempdir()?;
+        let shared_cat = create_keyed_catalog(dir.path()).await?;
+        let tasks = Arc::new(TaskRegistry::default());
+        let body = r#"{"query": {"range": {"rank": {"gte": 3}}}, "set": {"rank": "not a number"}}"#;
+        let resp = update_by_query(Arc::clone(&shared_cat), Arc::clone(&tasks), Body::from(body), "keyed").await?;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let created: TaskCreated = wait_json(resp).await;
+
+        let status = wait_for_task(&tasks, created.task).await;
+        assert_eq!(status.state, toshi_types::TaskState::Failed);
+        assert_eq!(status.written, 0);
+        assert_eq!(shared_cat.get_index("keyed")?.get_commit_status().pending_docs, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reindex() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let shared_cat = create_test_catalog("test_index");
+        let tasks = Arc::new(TaskRegistry::default());
+        let schema = r#"[
+            { "name": "body", "type": "text", "options": { "indexing": { "record": "position", "tokenizer": "default" }, "stored": true } },
+            { "name": "test_u64", "type": "u64", "options": { "indexed": true, "stored": true } },
+            { "name": "added", "type": "u64", "options": { "indexed": true, "stored": true } }
+         ]"#;
+        create_index(Arc::clone(&shared_cat), Body::from(schema), "reindex_dest").await?;
+
+        let body = r#"{"source": "test_index", "dest": "reindex_dest", "fields": {"test_text": "body"},
+            "query": {"range": {"test_u64": {"gte": 12}}}, "options": {"commit": true}}"#;
+        let resp = reindex(Arc::clone(&shared_cat), Arc::clone(&tasks), Body::from(body)).await?;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let created: TaskCreated = wait_json(resp).await;
+
+        let status = wait_for_task(&tasks, created.task).await;
+        assert_eq!(status.state, toshi_types::TaskState::Completed);
+        assert_eq!(status.written, 3);
+
+        wait_until("the reindexed documents", || async {
+            let search = r#"{"query": {"term": {"body": "document"}}, "limit": 10}"#;
+            let resp = crate::handlers::doc_search(Arc::clone(&shared_cat), Body::from(search), "reindex_dest").await;
+            wait_json::<crate::SearchResults>(resp.unwrap()).await.hits == 2
+        })
+        .await;
+
+        let body = r#"{"source": "test_index", "dest": "test_index"}"#;
+        let resp = reindex(Arc::clone(&shared_cat), Arc::clone(&tasks), Body::from(body)).await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        remove_dir_all::remove_dir_all("reindex_dest"); // Try, but don't fail on this.
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_bad_json() {
         let shared_cat = create_test_catalog("test_index");
@@ -156,7 +631,7 @@ mod tests {
             options: None,
         };
         let body_bytes = serde_json::to_vec(&add_doc).unwrap();
-        let req = add_document(Arc::clone(&shared_cat), Body::from(body_bytes), &test_index())
+        let req = add_document(Arc::clone(&shared_cat), Body::from(body_bytes), &test_index(), "")
             .await
             .unwrap()
             .into_body();
@@ -1,12 +1,16 @@
 use hyper::Body;
 
-pub use {bulk::*, index::*, list::*, root::*, search::*, summary::*};
+pub use {aliases::*, bulk::*, index::*, list::*, pipelines::*, root::*, search::*, summary::*, tasks::*, templates::*};
 
+pub mod aliases;
 pub mod bulk;
 pub mod index;
 pub mod list;
+pub mod pipelines;
 pub mod root;
 pub mod search;
 pub mod summary;
+pub mod tasks;
+pub mod templates;
 
 pub type ResponseFuture = Result<hyper::Response<Body>, hyper::Error>;
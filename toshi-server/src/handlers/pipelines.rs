@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use hyper::body::to_bytes;
+use hyper::{Body, StatusCode};
+
+use toshi_types::{Catalog, Error, Pipeline};
+
+use crate::handlers::ResponseFuture;
+use crate::utils::{empty_with_code, error_response, with_body};
+
+pub async fn get_pipeline<C: Catalog>(catalog: Arc<C>, name: &str) -> ResponseFuture {
+    match catalog.get_pipeline(name) {
+        Ok(pipeline) => Ok(with_body(pipeline)),
+        Err(e) => Ok(error_response(StatusCode::NOT_FOUND, e)),
+    }
+}
+
+pub async fn put_pipeline<C: Catalog>(catalog: Arc<C>, body: Body, name: &str) -> ResponseFuture {
+    let agg_body = to_bytes(body).await?;
+    match serde_json::from_slice::<Pipeline>(&agg_body) {
+        Ok(pipeline) => match catalog.put_pipeline(name, pipeline) {
+            Ok(_) => Ok(empty_with_code(StatusCode::OK)),
+            Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+        },
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, Error::from(e))),
+    }
+}
@@ -8,13 +8,108 @@ use log::info;
 use toshi_types::*;
 
 use crate::handlers::ResponseFuture;
-use crate::utils::{empty_with_code, with_body};
+use crate::utils::{empty_with_code, error_response, with_body};
+use crate::{Result, SearchResults};
+
+async fn search_indexes<C: Catalog>(catalog: &Arc<C>, search: Search, names: Vec<String>) -> Result<SearchResults> {
+    let (limit, order) = (search.limit, search.sort_order);
+    let mut handles = Vec::with_capacity(names.len());
+    for name in names {
+        let index = catalog.get_index(&name)?;
+        let search = search.clone();
+        handles.push(tokio::spawn(async move {
+            index.search_index(search).await.map(|results| results.with_index(&name))
+        }));
+    }
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in futures::future::join_all(handles).await {
+        results.push(handle.map_err(|_| Error::SpawnError)??);
+    }
+    Ok(SearchResults::merge(results, limit, order))
+}
+
+async fn multi_index_search<C: Catalog>(catalog: Arc<C>, search: Search, indexes: &str) -> ResponseFuture {
+    let names = match catalog.resolve_indexes(indexes) {
+        Ok(names) if names.is_empty() => return Ok(empty_with_code(StatusCode::NOT_FOUND)),
+        Ok(names) => names,
+        Err(e) => return Ok(error_response(StatusCode::NOT_FOUND, e)),
+    };
+    info!("Query: {:?} against {:?}", search, names);
+
+    match search_indexes(&catalog, search, names).await {
+        Ok(results) => Ok(with_body(results)),
+        Err(e) => Ok(Response::from(e)),
+    }
+}
+
+async fn run_search<C: Catalog>(catalog: Arc<C>, search: Search, index: String) -> Result<SearchResults> {
+    if catalog.is_multi_index(&index) {
+        let names = catalog.resolve_indexes(&index)?;
+        if names.is_empty() {
+            return Err(Error::UnknownIndex(index));
+        }
+        search_indexes(&catalog, search, names).await
+    } else {
+        catalog.get_index(&index)?.search_index(search).await
+    }
+}
+
+fn parse_search_pair(pair: &[&[u8]]) -> Result<(MultiSearchHeader, Search)> {
+    let header = serde_json::from_slice::<MultiSearchHeader>(pair[0])?;
+    let body = pair
+        .get(1)
+        .ok_or_else(|| Error::QueryError(format!("Missing search body for index: {}", header.index)))?;
+    let search = serde_json::from_slice::<Search>(body)?;
+    let search = if search.query.is_none() {
+        Search::all_limit(search.limit)
+    } else {
+        search
+    };
+    Ok((header, search))
+}
+
+/// Runs a batch of searches given as newline delimited header/body pairs, the results are
+/// returned in the same order as the searches were given.
+pub async fn multi_search<C: Catalog>(catalog: Arc<C>, body: Body) -> ResponseFuture {
+    let b = to_bytes(body).await?;
+    let lines: Vec<&[u8]> = b
+        .split(|c| *c == b'\n')
+        .filter(|l| !l.iter().all(u8::is_ascii_whitespace))
+        .collect();
+
+    let mut handles = Vec::with_capacity(lines.len() / 2 + 1);
+    for pair in lines.chunks(2) {
+        let request = parse_search_pair(pair);
+        let catalog = Arc::clone(&catalog);
+        handles.push(tokio::spawn(async move {
+            match request {
+                Ok((header, search)) => run_search(catalog, search, header.index).await,
+                Err(e) => Err(e),
+            }
+        }));
+    }
+
+    let responses: Vec<MultiSearchResult<FlatNamedDocument>> = futures::future::join_all(handles)
+        .await
+        .into_iter()
+        .map(|r| match r {
+            Ok(Ok(results)) => MultiSearchResult::Results(results),
+            Ok(Err(e)) => MultiSearchResult::Error(ErrorResponse::new(e)),
+            Err(_) => MultiSearchResult::Error(ErrorResponse::new(Error::SpawnError)),
+        })
+        .collect();
+    Ok(with_body(responses))
+}
 
 pub async fn doc_search<C: Catalog>(catalog: Arc<C>, body: Body, index: &str) -> ResponseFuture {
     let b = to_bytes(body).await?;
     match serde_json::from_slice::<Search>(&b) {
         Ok(req) => {
             let req = if req.query.is_none() { Search::all_limit(req.limit) } else { req };
+            if catalog.is_multi_index(index) {
+                return multi_index_search(catalog, req, index).await;
+            }
             if catalog.exists(index) {
                 info!("Query: {:?}", req);
                 let index = catalog.get_index(index).unwrap(); // If this unwrap fails, this is a bug.
@@ -30,6 +125,84 @@ pub async fn doc_search<C: Catalog>(catalog: Arc<C>, body: Body, index: &str) ->
     }
 }
 
+pub async fn doc_count<C: Catalog>(catalog: Arc<C>, body: Body, index: &str) -> ResponseFuture {
+    let b = to_bytes(body).await?;
+    let req = if b.is_empty() {
+        Ok(CountQuery::default())
+    } else {
+        serde_json::from_slice::<CountQuery>(&b)
+    };
+    match req {
+        Ok(req) => {
+            let names = if catalog.is_multi_index(index) {
+                match catalog.resolve_indexes(index) {
+                    Ok(names) => names,
+                    Err(e) => return Ok(error_response(StatusCode::NOT_FOUND, e)),
+                }
+            } else if catalog.exists(index) {
+                vec![index.to_string()]
+            } else {
+                return Ok(empty_with_code(StatusCode::NOT_FOUND));
+            };
+            let query = req.query.unwrap_or(Query::All);
+            info!("Count: {:?}", query);
+
+            let mut count = 0;
+            for name in names {
+                let result = match catalog.get_index(&name) {
+                    Ok(index) => index.count(query.clone()).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(c) => count += c.count,
+                    Err(e) => return Ok(Response::from(e)),
+                }
+            }
+            Ok(with_body(DocCount { count }))
+        }
+        Err(err) => Ok(Response::from(Error::QueryError(format!("Bad JSON Query: {}", err)))),
+    }
+}
+
+pub async fn suggest<C: Catalog>(catalog: Arc<C>, body: Body, index: &str) -> ResponseFuture {
+    if !catalog.exists(index) {
+        return Ok(empty_with_code(StatusCode::NOT_FOUND));
+    }
+    let b = to_bytes(body).await?;
+    match serde_json::from_slice::<SuggestRequest>(&b) {
+        Ok(req) => {
+            info!("Suggest: {:?}", req);
+            match catalog.get_index(index) {
+                Ok(index) => match index.suggest(req).await {
+                    Ok(suggestions) => Ok(with_body(suggestions)),
+                    Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+                },
+                Err(e) => Ok(error_response(StatusCode::NOT_FOUND, e)),
+            }
+        }
+        Err(err) => Ok(error_response(StatusCode::BAD_REQUEST, err.into())),
+    }
+}
+
+pub async fn complete<C: Catalog>(catalog: Arc<C>, index: &str, query: &str) -> ResponseFuture {
+    if !catalog.exists(index) {
+        return Ok(empty_with_code(StatusCode::NOT_FOUND));
+    }
+    match serde_urlencoded::from_str::<CompleteRequest>(query) {
+        Ok(req) => match catalog.get_index(index) {
+            Ok(index) => match index.complete(req).await {
+                Ok(completions) => Ok(with_body(completions)),
+                Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+            },
+            Err(e) => Ok(error_response(StatusCode::NOT_FOUND, e)),
+        },
+        Err(err) => Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            Error::QueryError(format!("Bad completion parameters: {}", err)),
+        )),
+    }
+}
+
 pub async fn all_docs<C: Catalog>(catalog: Arc<C>, index: &str) -> ResponseFuture {
     let body = Body::from(serde_json::to_vec(&Search::all_docs()).unwrap());
     doc_search(catalog, body, index).await
@@ -42,11 +215,14 @@ pub mod tests {
     use hyper::Body;
     use pretty_assertions::assert_eq;
 
-    use toshi_types::{ErrorResponse, ExactTerm, FuzzyQuery, FuzzyTerm, KeyValue, PhraseQuery, Query, Search, TermPair};
+    use toshi_types::{
+        Completion, DocCount, ErrorResponse, ExactTerm, FieldSuggestions, FlatNamedDocument, FuzzyQuery, FuzzyTerm, KeyValue,
+        MultiSearchResult, PhraseQuery, Query, Search, SortValue, TermPair,
+    };
 
     use crate::commit::tests::*;
-    use crate::handlers::{doc_search, ResponseFuture};
-    use crate::index::create_test_catalog;
+    use crate::handlers::{complete, doc_count, doc_search, multi_search, suggest, ResponseFuture};
+    use crate::index::{create_test_catalog, IndexCatalog};
     use crate::SearchResults;
 
     type ReturnUnit = Result<(), Box<dyn std::error::Error>>;
@@ -183,6 +359,148 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_multi_index_query() -> ReturnUnit {
+        let mut catalog = IndexCatalog::from_index("test_index".into(), create_test_index())?;
+        catalog.add_test_index("test_index_2".into(), create_test_index());
+        let cat = Arc::new(catalog);
+
+        let body = r#"{ "query" : { "term": { "test_text": "document" } }, "limit": 4 }"#;
+        let q = doc_search(Arc::clone(&cat), Body::from(body), "test_index,test_index_2").await?;
+        let results: SearchResults = wait_json(q).await;
+        assert_eq!(results.hits, 4);
+        assert!(results.get_docs().iter().all(|d| d.index.is_some()));
+        assert!(results.get_docs().windows(2).all(|w| w[0].score >= w[1].score));
+
+        let body = r#"{ "query" : { "term": { "test_text": "document" } } }"#;
+        let q = doc_search(Arc::clone(&cat), Body::from(body), "test_*").await?;
+        let results: SearchResults = wait_json(q).await;
+        assert_eq!(results.hits, 6);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_index_sort() -> ReturnUnit {
+        let sorted_index = |values: &[i64]| -> tantivy::Result<tantivy::Index> {
+            let mut builder = tantivy::schema::SchemaBuilder::new();
+            let ts = builder.add_i64_field("ts", tantivy::schema::FAST | tantivy::schema::STORED | tantivy::schema::INDEXED);
+            let index = tantivy::Index::create_in_ram(builder.build());
+            let mut writer = index.writer(15_000_000)?;
+            for v in values {
+                writer.add_document(tantivy::doc!(ts => *v))?;
+            }
+            writer.commit()?;
+            Ok(index)
+        };
+        let mut catalog = IndexCatalog::from_index("sorted_1".into(), sorted_index(&[-5, 1_600_000_000_000_003, 7])?)?;
+        catalog.add_test_index("sorted_2".into(), sorted_index(&[1_600_000_000_000_002, -9])?);
+        let cat = Arc::new(catalog);
+
+        let sorted = |order: &str| {
+            let body = format!(
+                r#"{{ "query" : {{ "range" : {{ "ts" : {{ "gte" : -100 }} }} }}, "limit": 3, "sort_by": "ts", "sort_order": "{}" }}"#,
+                order
+            );
+            let cat = Arc::clone(&cat);
+            async move {
+                let q = doc_search(cat, Body::from(body), "sorted_1,sorted_2").await.unwrap();
+                let results: SearchResults = wait_json(q).await;
+                results.get_docs().iter().filter_map(|d| d.sort_value).collect::<Vec<_>>()
+            }
+        };
+        let desc = sorted("desc").await;
+        assert_eq!(
+            desc,
+            vec![
+                SortValue::U64(1_600_000_000_000_003),
+                SortValue::U64(1_600_000_000_000_002),
+                SortValue::U64(7)
+            ]
+        );
+        let asc = sorted("asc").await;
+        assert_eq!(asc, vec![SortValue::I64(-9), SortValue::I64(-5), SortValue::U64(7)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_index_unknown() -> ReturnUnit {
+        let cat = create_test_catalog("test_index");
+        let body = r#"{ "query" : { "term": { "test_text": "document" } } }"#;
+        let q = doc_search(Arc::clone(&cat), Body::from(body), "test_index,asdf").await?;
+        assert_eq!(q.status(), hyper::StatusCode::NOT_FOUND);
+        let b: ErrorResponse = wait_json(q).await;
+        assert_eq!(b.message, "Unknown Index: 'asdf' does not exist");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_search() -> ReturnUnit {
+        let cat = create_test_catalog("test_index");
+        let body = r#"{"index": "test_index"}
+        { "query" : { "term": { "test_text": "document" } } }
+        {"index": "asdf"}
+        { "query" : { "term": { "test_text": "document" } } }
+        {"index": "test_index"}
+        { "query" : { "regex" : { "test_text" : "d[ou]{1}c[k]?ument" } }, "limit": 2 }"#;
+        let q = multi_search(Arc::clone(&cat), Body::from(body)).await?;
+        let results: Vec<MultiSearchResult<FlatNamedDocument>> = wait_json(q).await;
+        assert_eq!(results.len(), 3);
+        match (&results[0], &results[1], &results[2]) {
+            (MultiSearchResult::Results(first), MultiSearchResult::Error(err), MultiSearchResult::Results(third)) => {
+                assert_eq!(first.hits, 3);
+                assert_eq!(err.message, "Unknown Index: 'asdf' does not exist");
+                assert_eq!(third.hits, 2);
+            }
+            r => panic!("Unexpected multi search results: {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_count() -> ReturnUnit {
+        let cat = create_test_catalog("test_index");
+        let body = r#"{ "query" : { "term": { "test_text": "document" } } }"#;
+        let q = doc_count(Arc::clone(&cat), Body::from(body), "test_index").await?;
+        let count: DocCount = wait_json(q).await;
+        assert_eq!(count.count, 3);
+
+        let q = doc_count(Arc::clone(&cat), Body::empty(), "test_index").await?;
+        let count: DocCount = wait_json(q).await;
+        assert_eq!(count.count, 5);
+
+        let q = doc_count(Arc::clone(&cat), Body::empty(), "asdf").await?;
+        assert_eq!(q.status(), hyper::StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_suggest() -> ReturnUnit {
+        let cat = create_test_catalog("test_index");
+        let body = r#"{ "fields": { "test_text": "duckimant" }, "size": 2 }"#;
+        let q = suggest(Arc::clone(&cat), Body::from(body), "test_index").await?;
+        let b: std::collections::BTreeMap<String, FieldSuggestions> = wait_json(q).await;
+        assert_eq!(b["test_text"].did_you_mean.as_deref(), Some("duckiment"));
+        assert_eq!(b["test_text"].terms[0].options.len(), 1);
+
+        let body = r#"{ "fields": { "test_i64": "2014" } }"#;
+        let q = suggest(Arc::clone(&cat), Body::from(body), "test_index").await?;
+        assert_eq!(q.status(), hyper::StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_complete() -> ReturnUnit {
+        let cat = create_test_catalog("test_index");
+        let q = complete(Arc::clone(&cat), "test_index", "field=test_text&prefix=du&size=5").await?;
+        let b: Vec<Completion> = wait_json(q).await;
+        assert_eq!(b.len(), 1);
+        assert_eq!(b[0].term, "duckiment");
+
+        let q = complete(Arc::clone(&cat), "test_index", "prefix=du").await?;
+        assert_eq!(q.status(), hyper::StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_bool_query() -> ReturnUnit {
         let test_json = r#"{"query": { "bool": {
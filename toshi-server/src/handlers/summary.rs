@@ -6,7 +6,7 @@ use std::time::Instant;
 use toshi_types::*;
 
 use crate::handlers::ResponseFuture;
-use crate::utils::{empty_with_code, with_body};
+use crate::utils::{empty_with_code, error_response, with_body};
 use std::sync::Arc;
 
 #[derive(Serialize)]
@@ -14,7 +14,8 @@ struct FlushResponse {
     opstamp: u64,
 }
 
-pub async fn index_summary<C: Catalog>(catalog: Arc<C>, index: &str, options: QueryOptions) -> ResponseFuture {
+/// Summarize an index, `commit_duration` fills in the commit interval of indexes whose policy doesn't have one
+pub async fn index_summary<C: Catalog>(catalog: Arc<C>, index: &str, options: QueryOptions, commit_duration: f32) -> ResponseFuture {
     let start = Instant::now();
     if let Ok(index) = catalog.get_index(index) {
         let metas = index.get_index().load_metas().unwrap();
@@ -22,7 +23,11 @@ pub async fn index_summary<C: Catalog>(catalog: Arc<C>, index: &str, options: Qu
             SummaryResponse::new(metas, Some(index.get_space()))
         } else {
             SummaryResponse::new(metas, None)
-        };
+        }
+        .with_deleted_docs(index.get_deleted_docs());
+        let mut commit = index.get_commit_status();
+        commit.policy = commit.policy.with_default_interval(commit_duration);
+        let summary = summary.with_commit_status(commit);
         info!("Took: {:?}", start.elapsed());
         Ok(with_body(summary))
     } else {
@@ -32,11 +37,23 @@ pub async fn index_summary<C: Catalog>(catalog: Arc<C>, index: &str, options: Qu
     }
 }
 
+pub async fn index_schema<C: Catalog>(catalog: Arc<C>, index: &str) -> ResponseFuture {
+    match catalog.get_index(index) {
+        Ok(index) => Ok(with_body(SchemaBody(index.get_index().schema()))),
+        Err(e) => Ok(error_response(StatusCode::NOT_FOUND, e)),
+    }
+}
+
+pub async fn index_settings<C: Catalog>(catalog: Arc<C>, index: &str) -> ResponseFuture {
+    match catalog.get_index(index) {
+        Ok(index) => Ok(with_body(index.get_settings().await)),
+        Err(e) => Ok(error_response(StatusCode::NOT_FOUND, e)),
+    }
+}
+
 pub async fn flush<C: Catalog>(catalog: Arc<C>, index: &str) -> ResponseFuture {
     if let Ok(local_index) = catalog.get_index(index) {
-        let writer = local_index.get_writer();
-        let mut write = writer.lock().await;
-        let opstamp = write.commit().unwrap();
+        let opstamp = local_index.commit().await.unwrap();
         info!("Successful commit: {}", index);
         Ok(with_body(FlushResponse { opstamp }))
     } else {
@@ -44,3 +61,52 @@ pub async fn flush<C: Catalog>(catalog: Arc<C>, index: &str) -> ResponseFuture {
         Ok(empty_with_code(StatusCode::NOT_FOUND))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commit::tests::wait_json;
+    use crate::index::create_test_catalog;
+
+    #[tokio::test]
+    async fn test_index_schema() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let catalog = create_test_catalog("test_index");
+        let resp = index_schema(Arc::clone(&catalog), "test_index").await?;
+        let schema: SchemaBody = wait_json(resp).await;
+        assert_eq!(schema.0, catalog.get_index("test_index")?.get_index().schema());
+
+        let resp = index_schema(Arc::clone(&catalog), "missing").await?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_index_settings() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let catalog = create_test_catalog("test_index");
+        let resp = index_settings(Arc::clone(&catalog), "test_index").await?;
+        let settings: SettingsResponse = wait_json(resp).await;
+        assert_eq!(settings.reload_policy, "OnCommit");
+        assert_eq!(settings.tokenizers.get("test_text").map(String::as_str), Some("default"));
+        assert!(!settings.tokenizers.contains_key("test_u64"));
+        assert_eq!(settings.config, IndexConfig::default());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_summary_commit_status() -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let catalog = create_test_catalog("test_index");
+        let resp = index_summary(Arc::clone(&catalog), "test_index", QueryOptions::default(), 5.0).await?;
+        let summary: serde_json::Value = wait_json(resp).await;
+        let commit: CommitStatus = serde_json::from_value(summary["commit"].clone())?;
+        assert_eq!(commit.policy.interval, Some(5.0));
+        assert_eq!(commit.last_commit, None);
+
+        flush(Arc::clone(&catalog), "test_index").await?;
+        let resp = index_summary(Arc::clone(&catalog), "test_index", QueryOptions::default(), 5.0).await?;
+        let summary: serde_json::Value = wait_json(resp).await;
+        let commit: CommitStatus = serde_json::from_value(summary["commit"].clone())?;
+        assert!(commit.last_commit.is_some());
+        assert_eq!(commit.pending_docs, 0);
+        Ok(())
+    }
+}
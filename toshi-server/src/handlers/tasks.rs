@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use hyper::StatusCode;
+
+use toshi_types::Error;
+
+use crate::handlers::ResponseFuture;
+use crate::tasks::TaskRegistry;
+use crate::utils::{error_response, with_body};
+
+pub async fn task_status(tasks: Arc<TaskRegistry>, id: &str) -> ResponseFuture {
+    match id.parse::<u64>().ok().and_then(|id| tasks.status(id)) {
+        Some(status) => Ok(with_body(status)),
+        None => Ok(error_response(StatusCode::NOT_FOUND, Error::UnknownTask(id.into()))),
+    }
+}
+
+/// Ask a running task to stop between batches. A bulk insert with `on_error=rollback` rolls back its writes, other
+/// tasks keep whatever they wrote before they stopped.
+pub async fn cancel_task(tasks: Arc<TaskRegistry>, id: &str) -> ResponseFuture {
+    match id.parse::<u64>().ok().and_then(|id| tasks.cancel(id)) {
+        Some(status) => Ok(with_body(status)),
+        None => Ok(error_response(StatusCode::NOT_FOUND, Error::UnknownTask(id.into()))),
+    }
+}
@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use hyper::body::to_bytes;
+use hyper::{Body, StatusCode};
+
+use toshi_types::{Catalog, Error, IndexTemplate};
+
+use crate::handlers::ResponseFuture;
+use crate::utils::{empty_with_code, error_response, with_body};
+
+pub async fn get_template<C: Catalog>(catalog: Arc<C>, name: &str) -> ResponseFuture {
+    match catalog.get_templates().remove(name) {
+        Some(template) => Ok(with_body(template)),
+        None => Ok(error_response(StatusCode::NOT_FOUND, Error::UnknownTemplate(name.into()))),
+    }
+}
+
+pub async fn put_template<C: Catalog>(catalog: Arc<C>, body: Body, name: &str) -> ResponseFuture {
+    let agg_body = to_bytes(body).await?;
+    match serde_json::from_slice::<IndexTemplate>(&agg_body) {
+        Ok(template) => match catalog.put_template(name, template) {
+            Ok(_) => Ok(empty_with_code(StatusCode::OK)),
+            Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e)),
+        },
+        Err(e) => Ok(error_response(StatusCode::BAD_REQUEST, e.into())),
+    }
+}
@@ -1,21 +1,45 @@
 use std::clone::Clone;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
 
 use dashmap::DashMap;
 use tantivy::schema::Schema;
 use tantivy::Index;
 
-use toshi_types::{Catalog, Error};
+use tokio::sync::Mutex;
+
+use toshi_types::{AliasAction, Catalog, Error, IndexConfig, IndexHandle, IndexTemplate, Pipeline};
 
 use crate::handle::LocalIndex;
 use crate::settings::Settings;
+use crate::utils::wildcard_match;
 use crate::Result;
 
+/// The file under the data path that aliases are kept in
+pub const ALIASES_FILE: &str = "_aliases.json";
+/// The file under the data path that templates are kept in
+pub const TEMPLATES_FILE: &str = "_templates.json";
+/// The file under the data path that ingest pipelines are kept in
+pub const PIPELINES_FILE: &str = "_pipelines.json";
+
+/// How long removing an index waits for requests that are still using it
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Aliases = BTreeMap<String, BTreeSet<String>>;
+
 pub struct IndexCatalog {
     settings: Settings,
     base_path: PathBuf,
     local_handles: DashMap<String, LocalIndex>,
+    aliases: RwLock<Aliases>,
+    templates: RwLock<BTreeMap<String, IndexTemplate>>,
+    pipelines: RwLock<BTreeMap<String, Pipeline>>,
+    // Held while creating and removing indexes so concurrent writes to a new index only create it once, and a removed
+    // index isn't created again until its directory is gone
+    create_lock: Mutex<()>,
 }
 
 impl IndexCatalog {
@@ -36,15 +60,60 @@ impl Catalog for IndexCatalog {
         &self.local_handles
     }
 
-    async fn add_index(&self, name: &str, schema: Schema) -> Result<()> {
-        let handle = LocalIndex::new(
-            self.base_path.clone(),
-            name,
-            schema,
-            self.settings.writer_memory,
-            self.settings.get_merge_policy(),
-        )?;
-        self.local_handles.insert(name.to_string(), handle);
+    async fn add_index_with_config(&self, name: &str, schema: Schema, config: IndexConfig) -> Result<()> {
+        let _creating = self.create_lock.lock().await;
+        self.create_index(name, schema, config)
+    }
+
+    async fn remove_index(&self, name: &str) -> Result<()> {
+        // Held until the directory is gone, so the index can't be created again from a template in the meantime
+        let _creating = self.create_lock.lock().await;
+        // Taking the handle out of the collection first keeps the commit watcher from committing it again
+        let (_, mut handle) = self
+            .local_handles
+            .remove(name)
+            .ok_or_else(|| Error::UnknownIndex(name.to_string()))?;
+        // Checked once the handle is out of the collection so no new bulk insert or task can find it
+        if handle.in_bulk() {
+            self.local_handles.insert(name.to_string(), handle);
+            return Err(Error::IndexInUse(name.to_string()));
+        }
+        // Requests that got a handle before it was taken out still share its writer, so they're given time to finish
+        let deadline = Instant::now() + REMOVE_TIMEOUT;
+        let mut writer = loop {
+            match handle.try_into_writer() {
+                Ok(writer) => break writer,
+                Err(h) if Instant::now() < deadline => {
+                    handle = *h;
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                Err(h) => {
+                    self.local_handles.insert(name.to_string(), *h);
+                    return Err(Error::IndexInUse(name.to_string()));
+                }
+            }
+        };
+        // Nothing can write to the directory once the writer has thrown away its pending writes and its merges are done
+        writer.rollback()?;
+        writer.wait_merging_threads()?;
+
+        {
+            let mut aliases = self.aliases.write().unwrap();
+            let mut updated = aliases.clone();
+            updated.values_mut().for_each(|indexes| {
+                indexes.remove(name);
+            });
+            updated.retain(|_, indexes| !indexes.is_empty());
+            if updated != *aliases {
+                self.save_aliases(&updated)?;
+                *aliases = updated;
+            }
+        }
+
+        let path = self.base_path.join(name);
+        if path.exists() {
+            fs::remove_dir_all(path)?;
+        }
         Ok(())
     }
 
@@ -56,14 +125,145 @@ impl Catalog for IndexCatalog {
     }
 
     fn get_index(&self, name: &str) -> Result<Self::Handle> {
-        self.local_handles.get(name).map(|r| r.value().to_owned()).ok_or_else(|| {
-            let _ = &name;
-            Error::UnknownIndex(name.into())
-        })
+        if let Some(handle) = self.local_handles.get(name) {
+            return Ok(handle.value().to_owned());
+        }
+        let index = match self.aliases.read().unwrap().get(name) {
+            Some(indexes) if indexes.len() > 1 => return Err(Error::AmbiguousAlias(name.into())),
+            Some(indexes) => indexes.iter().next().cloned(),
+            None => None,
+        };
+        index
+            .and_then(|index| self.local_handles.get(&index).map(|r| r.value().to_owned()))
+            .ok_or_else(|| Error::UnknownIndex(name.into()))
     }
 
     fn exists(&self, index: &str) -> bool {
-        self.get_collection().contains_key(index)
+        self.get_collection().contains_key(index) || self.aliases.read().unwrap().contains_key(index)
+    }
+
+    fn resolve_indexes(&self, pattern: &str) -> Result<Vec<String>> {
+        let mut resolved: Vec<String> = Vec::new();
+        for part in pattern.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if part.contains('*') {
+                let mut matched: Vec<String> = self
+                    .local_handles
+                    .iter()
+                    .map(|e| e.key().to_owned())
+                    .filter(|k| wildcard_match(part, k))
+                    .collect();
+                matched.sort();
+                resolved.append(&mut matched);
+            } else if self.local_handles.contains_key(part) {
+                resolved.push(part.to_string());
+            } else if let Some(indexes) = self.aliases.read().unwrap().get(part) {
+                resolved.extend(indexes.iter().cloned());
+            } else {
+                return Err(Error::UnknownIndex(part.into()));
+            }
+        }
+        let mut seen = std::collections::HashSet::new();
+        resolved.retain(|r| seen.insert(r.clone()));
+        Ok(resolved)
+    }
+
+    fn get_aliases(&self) -> BTreeMap<String, Vec<String>> {
+        self.aliases
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(alias, indexes)| (alias.clone(), indexes.iter().cloned().collect()))
+            .collect()
+    }
+
+    fn update_aliases(&self, actions: Vec<AliasAction>) -> Result<()> {
+        let mut aliases = self.aliases.write().unwrap();
+        let mut updated = aliases.clone();
+        for action in actions {
+            match action {
+                AliasAction::Add(target) => {
+                    if !self.local_handles.contains_key(&target.index) {
+                        return Err(Error::UnknownIndex(target.index));
+                    }
+                    if self.local_handles.contains_key(&target.alias) {
+                        return Err(Error::AlreadyExists(target.alias));
+                    }
+                    updated.entry(target.alias).or_default().insert(target.index);
+                }
+                AliasAction::Remove(target) => {
+                    let indexes = updated
+                        .get_mut(&target.alias)
+                        .ok_or_else(|| Error::UnknownAlias(target.alias.clone()))?;
+                    if !indexes.remove(&target.index) {
+                        return Err(Error::UnknownIndex(target.index));
+                    }
+                    if indexes.is_empty() {
+                        updated.remove(&target.alias);
+                    }
+                }
+            }
+        }
+        self.save_aliases(&updated)?;
+        *aliases = updated;
+        Ok(())
+    }
+
+    fn get_templates(&self) -> BTreeMap<String, IndexTemplate> {
+        self.templates.read().unwrap().clone()
+    }
+
+    fn put_template(&self, name: &str, template: IndexTemplate) -> Result<()> {
+        if template.index_patterns.is_empty() {
+            return Err(Error::InvalidConfig(format!("Template {} has no index patterns", name)));
+        }
+        LocalIndex::primary_key_field(&template.schema.0, &template.config)?;
+        template.config.commit_policy.validate()?;
+        let mut templates = self.templates.write().unwrap();
+        let mut updated = templates.clone();
+        updated.insert(name.into(), template);
+        fs::write(self.base_path.join(TEMPLATES_FILE), serde_json::to_vec_pretty(&updated)?)?;
+        *templates = updated;
+        Ok(())
+    }
+
+    fn get_pipelines(&self) -> BTreeMap<String, Pipeline> {
+        self.pipelines.read().unwrap().clone()
+    }
+
+    fn put_pipeline(&self, name: &str, pipeline: Pipeline) -> Result<()> {
+        pipeline.validate()?;
+        let mut pipelines = self.pipelines.write().unwrap();
+        let mut updated = pipelines.clone();
+        updated.insert(name.into(), pipeline);
+        fs::write(self.base_path.join(PIPELINES_FILE), serde_json::to_vec_pretty(&updated)?)?;
+        *pipelines = updated;
+        Ok(())
+    }
+
+    async fn get_or_create_index(&self, name: &str) -> Result<Self::Handle> {
+        if self.exists(name) {
+            return self.get_index(name);
+        }
+        let _creating = self.create_lock.lock().await;
+        if self.exists(name) {
+            return self.get_index(name);
+        }
+        let template = self
+            .templates
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, t)| t.index_patterns.iter().any(|p| wildcard_match(p, name)))
+            .max_by_key(|(template_name, t)| (t.priority, std::cmp::Reverse(template_name.to_string())))
+            .map(|(_, t)| t.clone());
+        match template {
+            Some(template) => {
+                log::info!("Creating index {} from a template", name);
+                self.create_index(name, template.schema.0, template.config)?;
+                self.get_index(name)
+            }
+            None => Err(Error::UnknownIndex(name.into())),
+        }
     }
 }
 
@@ -75,11 +275,32 @@ impl IndexCatalog {
             settings,
             base_path: path,
             local_handles: local_idxs,
+            aliases: RwLock::new(Aliases::new()),
+            templates: RwLock::new(BTreeMap::new()),
+            pipelines: RwLock::new(BTreeMap::new()),
+            create_lock: Mutex::new(()),
         };
 
         Ok(index_cat)
     }
 
+    /// Create an index on disk and add it to the catalog, callers must hold `create_lock`
+    fn create_index(&self, name: &str, schema: Schema, config: IndexConfig) -> Result<()> {
+        if self.aliases.read().unwrap().contains_key(name) {
+            return Err(Error::AlreadyExists(name.to_string()));
+        }
+        let handle = LocalIndex::new(
+            self.base_path.clone(),
+            name,
+            schema,
+            self.settings.writer_memory,
+            self.settings.get_merge_policy(),
+            config,
+        )?;
+        self.local_handles.insert(name.to_string(), handle);
+        Ok(())
+    }
+
     pub fn load_index(path: &str) -> Result<Index> {
         let p = PathBuf::from(path);
         if p.exists() {
@@ -106,12 +327,13 @@ impl IndexCatalog {
             let entry = dir?.path();
             if let Some(entry_str) = entry.to_str() {
                 if entry.exists() {
-                    if !entry_str.ends_with(".node_id") {
+                    if entry.is_dir() {
                         let pth: String = entry_str.rsplit(MAIN_SEPARATOR).take(1).collect();
                         log::debug!("Loading Path: {} - {}", pth, entry_str);
 
                         let idx = IndexCatalog::load_index(entry_str)?;
-                        self.add_index(&pth, idx.schema()).await?;
+                        let config = LocalIndex::read_config(&entry)?;
+                        self.add_index_with_config(&pth, idx.schema(), config).await?;
                     }
                 } else {
                     return Err(Error::UnknownIndex(format!("Path {}", entry.display())));
@@ -120,6 +342,37 @@ impl IndexCatalog {
                 return Err(Error::UnknownIndex(format!("Path {} is not a valid unicode path", entry.display())));
             }
         }
+
+        let mut aliases = IndexCatalog::read_aliases(&self.base_path)?;
+        aliases
+            .values_mut()
+            .for_each(|indexes| indexes.retain(|i| self.local_handles.contains_key(i)));
+        aliases.retain(|_, indexes| !indexes.is_empty());
+        *self.aliases.get_mut().unwrap() = aliases;
+
+        let templates = self.base_path.join(TEMPLATES_FILE);
+        if templates.exists() {
+            *self.templates.get_mut().unwrap() = serde_json::from_slice(&fs::read(templates)?)?;
+        }
+        let pipelines = self.base_path.join(PIPELINES_FILE);
+        if pipelines.exists() {
+            *self.pipelines.get_mut().unwrap() = serde_json::from_slice(&fs::read(pipelines)?)?;
+        }
+        Ok(())
+    }
+
+    /// Read the aliases kept under a data path, a missing file means there are none
+    pub fn read_aliases(base_path: &Path) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        let path = base_path.join(ALIASES_FILE);
+        if path.exists() {
+            Ok(serde_json::from_slice(&fs::read(path)?)?)
+        } else {
+            Ok(Aliases::new())
+        }
+    }
+
+    fn save_aliases(&self, aliases: &Aliases) -> Result<()> {
+        fs::write(self.base_path.join(ALIASES_FILE), serde_json::to_vec_pretty(aliases)?)?;
         Ok(())
     }
 
@@ -144,6 +397,10 @@ impl IndexCatalog {
             settings,
             base_path: PathBuf::new(),
             local_handles: map,
+            aliases: RwLock::new(Aliases::new()),
+            templates: RwLock::new(BTreeMap::new()),
+            pipelines: RwLock::new(BTreeMap::new()),
+            create_lock: Mutex::new(()),
         })
     }
 }
@@ -154,3 +411,67 @@ pub fn create_test_catalog(name: &str) -> crate::SharedCatalog {
     let catalog = IndexCatalog::from_index(name.into(), idx).unwrap();
     std::sync::Arc::new(catalog)
 }
+
+/// A test catalog whose new indexes and saved files go in `base_path` instead of the working directory
+#[cfg(test)]
+pub fn create_test_catalog_at(name: &str, base_path: &Path) -> crate::SharedCatalog {
+    let idx = crate::commit::tests::create_test_index();
+    let mut catalog = IndexCatalog::from_index(name.into(), idx).unwrap();
+    catalog.base_path = base_path.to_path_buf();
+    std::sync::Arc::new(catalog)
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::schema::{SchemaBuilder, TEXT};
+
+    use toshi_types::AliasTarget;
+
+    use super::*;
+
+    fn alias(action: fn(AliasTarget) -> AliasAction, index: &str) -> AliasAction {
+        action(AliasTarget {
+            index: index.into(),
+            alias: "logs".into(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_aliases() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path();
+        let settings = Settings {
+            path: format!("{}/", path.display()),
+            ..Default::default()
+        };
+        let catalog = IndexCatalog::new(settings)?;
+        catalog.add_index("logs_v1", text_schema()).await?;
+        catalog.add_index("logs_v2", text_schema()).await?;
+
+        catalog.update_aliases(vec![alias(AliasAction::Add, "logs_v1")])?;
+        assert_eq!(catalog.get_index("logs")?.get_name(), "logs_v1");
+        catalog.update_aliases(vec![alias(AliasAction::Remove, "logs_v1"), alias(AliasAction::Add, "logs_v2")])?;
+        assert_eq!(catalog.get_index("logs")?.get_name(), "logs_v2");
+
+        let failed = catalog.update_aliases(vec![alias(AliasAction::Add, "logs_v1"), alias(AliasAction::Add, "missing")]);
+        assert!(failed.is_err());
+        assert_eq!(catalog.resolve_indexes("logs")?, vec!["logs_v2"]);
+
+        catalog.update_aliases(vec![alias(AliasAction::Add, "logs_v1")])?;
+        assert!(catalog.is_multi_index("logs"));
+        assert!(matches!(catalog.get_index("logs"), Err(Error::AmbiguousAlias(_))));
+        assert_eq!(catalog.resolve_indexes("logs")?, vec!["logs_v1", "logs_v2"]);
+        assert_eq!(IndexCatalog::read_aliases(path)?["logs"].len(), 2);
+
+        catalog.remove_index("logs_v1").await?;
+        assert_eq!(catalog.get_aliases()["logs"], vec!["logs_v2"]);
+        assert!(catalog.add_index("logs", text_schema()).await.is_err());
+        Ok(())
+    }
+
+    fn text_schema() -> Schema {
+        let mut builder = SchemaBuilder::new();
+        builder.add_text_field("text", TEXT);
+        builder.build()
+    }
+}
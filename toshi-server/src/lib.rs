@@ -19,6 +19,8 @@ pub mod index;
 pub mod router;
 pub mod settings;
 pub mod shutdown;
+pub mod suggest;
+pub mod tasks;
 pub mod utils;
 
 pub type Result<T> = std::result::Result<T, toshi_types::Error>;
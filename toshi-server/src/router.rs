@@ -1,10 +1,9 @@
 use std::convert::Infallible;
 use std::net::{SocketAddr, TcpListener};
-use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Method, Request, Response, Server};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
 
 use log::*;
 use tower_util::BoxService;
@@ -13,29 +12,37 @@ use toshi_types::{Catalog, QueryOptions};
 
 use crate::handlers::*;
 use crate::settings::Settings;
-use crate::utils::{not_found, parse_path};
+use crate::tasks::TaskRegistry;
+use crate::utils::{decode_body, encode_response, error_response, not_found, parse_path};
 
 pub type BoxedFn = BoxService<Request<Body>, Response<Body>, hyper::Error>;
 
 #[derive(Clone)]
 pub struct Router<C: Catalog> {
     pub cat: Arc<C>,
-    pub watcher: Arc<AtomicBool>,
     pub settings: Settings,
+    pub tasks: Arc<TaskRegistry>,
+    pub parsers: Arc<ParserPool>,
 }
 
 impl<C: Catalog> Router<C> {
-    pub fn new(cat: Arc<C>, watcher: Arc<AtomicBool>) -> Self {
-        Self::from_settings(cat, watcher, Settings::default())
+    pub fn new(cat: Arc<C>) -> Self {
+        Self::from_settings(cat, Settings::default())
     }
 
-    pub fn from_settings(cat: Arc<C>, watcher: Arc<AtomicBool>, settings: Settings) -> Self {
-        Self { cat, watcher, settings }
+    pub fn from_settings(cat: Arc<C>, settings: Settings) -> Self {
+        Self {
+            cat,
+            parsers: Arc::new(ParserPool::new(settings.json_parsing_threads)),
+            settings,
+            tasks: Arc::new(TaskRegistry::default()),
+        }
     }
 
     pub async fn route(
         catalog: Arc<C>,
-        watcher: Arc<AtomicBool>,
+        tasks: Arc<TaskRegistry>,
+        parsers: Arc<ParserPool>,
         req: Request<Body>,
         settings: Settings,
     ) -> Result<Response<Body>, hyper::Error> {
@@ -48,18 +55,54 @@ impl<C: Catalog> Router<C> {
 
         let method = parts.method;
         let path = parse_path(parts.uri.path());
+        let query = parts.uri.query().unwrap_or_default();
+        let body = match decode_body(&parts.headers, body) {
+            Ok(body) => body,
+            Err(e) => return Ok(error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, e)),
+        };
 
-        match (&method, &path[..]) {
+        let response = match (&method, &path[..]) {
             (m, ["_list"]) if m == Method::GET => list_indexes(catalog).await,
+            (m, ["_aliases"]) if m == Method::GET => list_aliases(catalog).await,
+            (m, ["_aliases"]) if m == Method::PUT => update_aliases(catalog, body).await,
+            (m, ["_template", name]) if m == Method::GET => get_template(catalog, name).await,
+            (m, ["_template", name]) if m == Method::PUT => put_template(catalog, body, name).await,
+            (m, ["_pipeline", name]) if m == Method::GET => get_pipeline(catalog, name).await,
+            (m, ["_pipeline", name]) if m == Method::PUT => put_pipeline(catalog, body, name).await,
+            (m, ["_bulk"]) if m == Method::POST => bulk_actions(catalog, body, query).await,
+            (m, ["_msearch"]) if m == Method::POST => multi_search(catalog, body).await,
+            (m, ["_reindex"]) if m == Method::POST => reindex(catalog, tasks, body).await,
+            (m, ["_tasks", id]) if m == Method::GET => task_status(tasks, id).await,
+            (m, ["_tasks", id]) if m == Method::DELETE => cancel_task(tasks, id).await,
             (m, [idx, "_create"]) if m == Method::PUT => create_index(catalog, body, idx).await,
-            (m, [idx, "_summary"]) if m == Method::GET => index_summary(catalog, idx, query_options).await,
+            (m, [idx, "_drop"]) if m == Method::DELETE => drop_index(catalog, idx).await,
+            (m, [idx, "_schema"]) if m == Method::GET => index_schema(catalog, idx).await,
+            (m, [idx, "_settings"]) if m == Method::GET => index_settings(catalog, idx).await,
+            (m, [idx, "_summary"]) if m == Method::GET => index_summary(catalog, idx, query_options, settings.auto_commit_duration).await,
             (m, [idx, "_flush"]) if m == Method::GET => flush(catalog, idx).await,
+            (m, [idx, "_count"]) if m == Method::POST => doc_count(catalog, body, idx).await,
+            (m, [idx, "_suggest"]) if m == Method::POST => suggest(catalog, body, idx).await,
+            (m, [idx, "_complete"]) if m == Method::GET => complete(catalog, idx, query).await,
+            (m, [idx, "_delete_by_query"]) if m == Method::POST => delete_by_query(catalog, body, idx).await,
+            (m, [idx, "_update_by_query"]) if m == Method::POST => update_by_query(catalog, tasks, body, idx).await,
+            (m, [idx, "_doc", id]) if m == Method::GET => get_document(catalog, idx, id).await,
+            (m, [idx, "_doc", id]) if m == Method::PUT => put_document(catalog, body, idx, id, query).await,
+            (m, [idx, "_update", id]) if m == Method::POST => update_document(catalog, body, idx, id).await,
             (m, [idx, "_bulk"]) if m == Method::POST => {
-                let w = Arc::clone(&watcher);
-                bulk_insert(catalog, w, body, idx, settings.json_parsing_threads, settings.max_line_length).await
+                bulk_insert(
+                    catalog,
+                    tasks,
+                    body,
+                    idx,
+                    query,
+                    parsers,
+                    settings.bulk_buffer_size,
+                    settings.max_line_length,
+                )
+                .await
             }
             (m, [idx]) if m == Method::POST => doc_search(catalog, body, idx).await,
-            (m, [idx]) if m == Method::PUT => add_document(catalog, body, idx).await,
+            (m, [idx]) if m == Method::PUT => add_document(catalog, body, idx, query).await,
             (m, [idx]) if m == Method::DELETE => delete_term(catalog, body, idx).await,
             (m, [idx]) if m == Method::GET => {
                 if idx == &"favicon.ico" {
@@ -70,18 +113,37 @@ impl<C: Catalog> Router<C> {
             }
             (m, []) if m == Method::GET => root().await,
             _ => not_found().await,
-        }
+        }?;
+        encode_response(response, &parts.headers).await
     }
 
-    pub async fn service_call(catalog: Arc<C>, watcher: Arc<AtomicBool>, settings: Settings) -> Result<BoxedFn, Infallible> {
+    pub async fn service_call(
+        catalog: Arc<C>,
+        tasks: Arc<TaskRegistry>,
+        parsers: Arc<ParserPool>,
+        settings: Settings,
+    ) -> Result<BoxedFn, Infallible> {
         Ok(BoxService::new(service_fn(move |req| {
             info!("REQ = {:?}", &req);
-            Self::route(Arc::clone(&catalog), Arc::clone(&watcher), req, settings.clone())
+            Self::route(
+                Arc::clone(&catalog),
+                Arc::clone(&tasks),
+                Arc::clone(&parsers),
+                req,
+                settings.clone(),
+            )
         })))
     }
 
     pub async fn router_with_catalog(self, addr: SocketAddr) -> Result<(), hyper::Error> {
-        let routes = make_service_fn(move |_| Self::service_call(Arc::clone(&self.cat), Arc::clone(&self.watcher), self.settings.clone()));
+        let routes = make_service_fn(move |_| {
+            Self::service_call(
+                Arc::clone(&self.cat),
+                Arc::clone(&self.tasks),
+                Arc::clone(&self.parsers),
+                self.settings.clone(),
+            )
+        });
         let server = Server::bind(&addr).serve(routes);
         if let Err(err) = server.await {
             trace!("server error: {}", err);
@@ -91,7 +153,14 @@ impl<C: Catalog> Router<C> {
 
     #[allow(dead_code)]
     pub(crate) async fn router_from_tcp(self, listener: TcpListener) -> Result<(), hyper::Error> {
-        let routes = make_service_fn(move |_| Self::service_call(Arc::clone(&self.cat), Arc::clone(&self.watcher), self.settings.clone()));
+        let routes = make_service_fn(move |_| {
+            Self::service_call(
+                Arc::clone(&self.cat),
+                Arc::clone(&self.tasks),
+                Arc::clone(&self.parsers),
+                self.settings.clone(),
+            )
+        });
         let server = Server::from_tcp(listener)?.serve(routes);
         if let Err(err) = server.await {
             trace!("server error: {}", err);
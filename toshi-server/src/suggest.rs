@@ -0,0 +1,236 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use levenshtein_automata::{Distance, LevenshteinAutomatonBuilder, DFA, SINK_STATE};
+use tantivy::schema::{Field, FieldType};
+use tantivy::{Index, Searcher};
+use tantivy_fst::Automaton;
+
+use toshi_types::{CompleteRequest, Completion, Error, FieldSuggestions, SuggestRequest, Suggestion, TermSuggestions};
+
+use crate::Result;
+
+/// Building Levenshtein automata gets very expensive past this distance
+pub const MAX_SUGGEST_DISTANCE: u8 = 2;
+
+/// Lets a Levenshtein DFA drive a search over a segment's term dictionary
+struct DfaWrapper<'a>(&'a DFA);
+
+impl Automaton for DfaWrapper<'_> {
+    type State = u32;
+
+    fn start(&self) -> Self::State {
+        self.0.initial_state()
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        matches!(self.0.distance(*state), Distance::Exact(_))
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        *state != SINK_STATE
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        self.0.transition(*state, byte)
+    }
+}
+
+/// Suggest corrections for every field of a request
+pub fn suggest(index: &Index, searcher: &Searcher, request: &SuggestRequest) -> Result<BTreeMap<String, FieldSuggestions>> {
+    if request.distance > MAX_SUGGEST_DISTANCE {
+        return Err(Error::QueryError(format!(
+            "Suggestion distance {} is larger than the max of {}",
+            request.distance, MAX_SUGGEST_DISTANCE
+        )));
+    }
+    let builder = LevenshteinAutomatonBuilder::new(request.distance, request.transposition);
+    request
+        .fields
+        .iter()
+        .map(|(field, text)| suggest_field(index, searcher, &builder, field, text, request.size).map(|s| (field.clone(), s)))
+        .collect()
+}
+
+/// Complete a prefix from the terms of a field, the most frequent terms across all segments come first. A prefix
+/// without any terms in it is refused, since completing it would mean reading the field's whole term dictionary.
+pub fn complete(index: &Index, searcher: &Searcher, request: &CompleteRequest) -> Result<Vec<Completion>> {
+    let field = text_field(index, &request.field)?;
+    let mut tokens = analyze(index, field, &request.prefix)?;
+    let prefix = match tokens.len() {
+        0 => return Err(Error::QueryError(format!("Prefix '{}' has no terms to complete", request.prefix))),
+        1 => tokens.remove(0),
+        _ => request.prefix.clone(),
+    };
+
+    let mut doc_freqs: HashMap<String, u64> = HashMap::new();
+    for segment in searcher.segment_readers() {
+        let inverted_index = segment.inverted_index(field)?;
+        let mut stream = inverted_index.terms().range().ge(prefix.as_bytes()).into_stream()?;
+        while stream.advance() {
+            if !stream.key().starts_with(prefix.as_bytes()) {
+                break;
+            }
+            if let Ok(term) = std::str::from_utf8(stream.key()) {
+                *doc_freqs.entry(term.to_string()).or_insert(0) += u64::from(stream.value().doc_freq);
+            }
+        }
+    }
+
+    let mut completions: Vec<Completion> = doc_freqs
+        .into_iter()
+        .map(|(term, doc_freq)| Completion { term, doc_freq })
+        .collect();
+    completions.sort_by(|a, b| (Reverse(a.doc_freq), &a.term).cmp(&(Reverse(b.doc_freq), &b.term)));
+    completions.truncate(request.size);
+    Ok(completions)
+}
+
+/// Look up a field that has its terms indexed as text
+fn text_field(index: &Index, field_name: &str) -> Result<Field> {
+    let schema = index.schema();
+    let field = schema
+        .get_field(field_name)
+        .ok_or_else(|| Error::UnknownIndexField(field_name.into()))?;
+    match schema.get_field_entry(field).field_type() {
+        FieldType::Str(opts) if opts.get_indexing_options().is_some() => Ok(field),
+        _ => Err(Error::QueryError(format!("Field: {} is not an indexed text field", field_name))),
+    }
+}
+
+/// Split text into terms the same way the field's tokenizer does when indexing
+fn analyze(index: &Index, field: Field, text: &str) -> Result<Vec<String>> {
+    let analyzer = index.tokenizer_for_field(field)?;
+    let mut tokens = Vec::new();
+    let mut stream = analyzer.token_stream(text);
+    while stream.advance() {
+        tokens.push(stream.token().text.clone());
+    }
+    Ok(tokens)
+}
+
+fn suggest_field(
+    index: &Index,
+    searcher: &Searcher,
+    builder: &LevenshteinAutomatonBuilder,
+    field_name: &str,
+    text: &str,
+    size: usize,
+) -> Result<FieldSuggestions> {
+    let field = text_field(index, field_name)?;
+    let tokens = analyze(index, field, text)?;
+
+    let mut corrected = Vec::with_capacity(tokens.len());
+    let mut terms = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let (exists, options) = candidates(searcher, field, builder, &token, size)?;
+        match options.first() {
+            Some(best) if !exists => corrected.push(Some(best.term.clone())),
+            _ => corrected.push(None),
+        }
+        terms.push(TermSuggestions { text: token, options });
+    }
+
+    let did_you_mean = if corrected.iter().any(Option::is_some) {
+        let words: Vec<&str> = corrected
+            .iter()
+            .zip(terms.iter())
+            .map(|(c, t)| c.as_deref().unwrap_or(&t.text))
+            .collect();
+        Some(words.join(" "))
+    } else {
+        None
+    };
+    Ok(FieldSuggestions { did_you_mean, terms })
+}
+
+/// Walk every segment's term dictionary with a Levenshtein automaton for the token, returning whether the
+/// token itself is indexed along with the closest and most frequent terms within the edit distance.
+fn candidates(
+    searcher: &Searcher,
+    field: Field,
+    builder: &LevenshteinAutomatonBuilder,
+    token: &str,
+    size: usize,
+) -> Result<(bool, Vec<Suggestion>)> {
+    let dfa = builder.build_dfa(token);
+    let mut doc_freqs: HashMap<String, u64> = HashMap::new();
+    for segment in searcher.segment_readers() {
+        let inverted_index = segment.inverted_index(field)?;
+        let mut stream = inverted_index.terms().search(DfaWrapper(&dfa)).into_stream()?;
+        while stream.advance() {
+            if let Ok(term) = std::str::from_utf8(stream.key()) {
+                *doc_freqs.entry(term.to_string()).or_insert(0) += u64::from(stream.value().doc_freq);
+            }
+        }
+    }
+
+    let exists = doc_freqs.remove(token).is_some();
+    let mut options: Vec<Suggestion> = doc_freqs
+        .into_iter()
+        .map(|(term, doc_freq)| {
+            let distance = match dfa.eval(&term) {
+                Distance::Exact(d) | Distance::AtLeast(d) => d,
+            };
+            Suggestion { term, distance, doc_freq }
+        })
+        .collect();
+    options.sort_by(|a, b| (a.distance, Reverse(a.doc_freq), &a.term).cmp(&(b.distance, Reverse(b.doc_freq), &b.term)));
+    options.truncate(size);
+    Ok((exists, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commit::tests::create_test_index;
+
+    #[test]
+    fn test_suggest() -> Result<()> {
+        let index = create_test_index();
+        let searcher = index.reader()?.searcher();
+        let mut fields = HashMap::new();
+        fields.insert("test_text".to_string(), "Test Documnet".to_string());
+        let request = SuggestRequest::new(fields);
+
+        let suggestions = suggest(&index, &searcher, &request)?;
+        let suggestions = &suggestions["test_text"];
+        assert_eq!(suggestions.did_you_mean.as_deref(), Some("test document"));
+        assert_eq!(suggestions.terms.len(), 2);
+        assert!(suggestions.terms[0].options.iter().all(|o| o.term != "test"));
+        let best = &suggestions.terms[1].options[0];
+        assert_eq!(best.term, "document");
+        assert_eq!(best.distance, 1);
+        assert_eq!(best.doc_freq, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_complete() -> Result<()> {
+        let index = create_test_index();
+        let searcher = index.reader()?.searcher();
+
+        let completions = complete(&index, &searcher, &CompleteRequest::new("test_text", "Doc", 10))?;
+        let terms: Vec<&str> = completions.iter().map(|c| c.term.as_str()).collect();
+        assert_eq!(terms, vec!["document", "dockument"]);
+        assert_eq!(completions[0].doc_freq, 3);
+
+        let completions = complete(&index, &searcher, &CompleteRequest::new("test_text", "d", 1))?;
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].term, "document");
+
+        assert!(complete(&index, &searcher, &CompleteRequest::new("test_u64", "1", 1)).is_err());
+        assert!(complete(&index, &searcher, &CompleteRequest::new("test_text", "", 1)).is_err());
+        assert!(complete(&index, &searcher, &CompleteRequest::new("test_text", " - ", 1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggest_bad_distance() {
+        let index = create_test_index();
+        let searcher = index.reader().unwrap().searcher();
+        let mut request = SuggestRequest::new(HashMap::new());
+        request.distance = 3;
+        assert!(suggest(&index, &searcher, &request).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use dashmap::DashMap;
+use log::*;
+
+use toshi_types::{Error, TaskProgress, TaskState, TaskStatus};
+
+use crate::Result;
+
+/// The most finished tasks whose status is kept around, the oldest are forgotten first
+const MAX_FINISHED_TASKS: usize = 1000;
+
+struct Task {
+    action: String,
+    progress: Arc<TaskProgress>,
+    state: TaskState,
+    error: Option<String>,
+}
+
+/// Keeps track of the background jobs started through the API so their progress can be looked up by id. Running
+/// tasks are always kept, finished ones only until `max_finished` more tasks have finished after them.
+pub struct TaskRegistry {
+    next_id: AtomicU64,
+    tasks: DashMap<u64, Task>,
+    finished: Mutex<VecDeque<u64>>,
+    max_finished: usize,
+}
+
+impl Default for TaskRegistry {
+    fn default() -> Self {
+        Self::with_max_finished(MAX_FINISHED_TASKS)
+    }
+}
+
+impl TaskRegistry {
+    /// A registry keeping the status of at most `max_finished` finished tasks
+    pub fn with_max_finished(max_finished: usize) -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            tasks: DashMap::new(),
+            finished: Mutex::new(VecDeque::new()),
+            max_finished,
+        }
+    }
+
+    /// Run a job in the background, returning the id its status can be looked up with. A job that stops early
+    /// because it was cancelled returns `Error::TaskCancelled`, one that finishes its work anyway is completed.
+    pub fn spawn<F>(self: &Arc<Self>, action: &str, progress: Arc<TaskProgress>, job: F) -> u64
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let task = Task {
+            action: action.into(),
+            progress,
+            state: TaskState::Running,
+            error: None,
+        };
+        self.tasks.insert(id, task);
+
+        let registry = Arc::clone(self);
+        // The job runs as a task of its own so a panic in it still leaves the task marked as failed
+        let job = tokio::spawn(job);
+        tokio::spawn(async move {
+            let result = job.await;
+            if let Some(mut task) = registry.tasks.get_mut(&id) {
+                let failure = match result {
+                    Ok(Err(Error::TaskCancelled)) => {
+                        task.state = TaskState::Cancelled;
+                        None
+                    }
+                    Ok(Ok(_)) => {
+                        task.state = TaskState::Completed;
+                        None
+                    }
+                    Ok(Err(e)) => Some(e.to_string()),
+                    Err(e) => Some(format!("The task stopped unexpectedly: {}", e)),
+                };
+                if let Some(e) = failure {
+                    error!("Task {} ({}) failed: {}", id, task.action, e);
+                    task.state = TaskState::Failed;
+                    task.error = Some(e);
+                }
+            }
+            registry.record_finished(id);
+        });
+        id
+    }
+
+    /// Remember that a task finished, forgetting the oldest finished tasks once there are too many
+    fn record_finished(&self, id: u64) {
+        let mut finished = self.finished.lock().unwrap();
+        finished.push_back(id);
+        while finished.len() > self.max_finished {
+            if let Some(oldest) = finished.pop_front() {
+                self.tasks.remove(&oldest);
+            }
+        }
+    }
+
+    /// Ask a task to stop, returning its status or `None` when there is no such task. Tasks that already
+    /// finished are left as they were.
+    pub fn cancel(&self, id: u64) -> Option<TaskStatus> {
+        let task = self.tasks.get(&id)?;
+        if task.state == TaskState::Running {
+            task.progress.cancel();
+        }
+        Some(task.progress.status(id, &task.action, task.state, task.error.clone()))
+    }
+
+    /// The current status of a task
+    pub fn status(&self, id: u64) -> Option<TaskStatus> {
+        self.tasks
+            .get(&id)
+            .map(|task| task.progress.status(id, &task.action, task.state, task.error.clone()))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Wait for a task to stop running, failing the test if it takes more than a few seconds
+    pub async fn wait_for_task(tasks: &TaskRegistry, id: u64) -> TaskStatus {
+        for _ in 0..500 {
+            match tasks.status(id) {
+                Some(status) if status.state != TaskState::Running => return status,
+                _ => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+        panic!("Task {} is still running", id);
+    }
+
+    #[tokio::test]
+    async fn test_task_states() {
+        let registry = Arc::new(TaskRegistry::default());
+        let progress = Arc::new(TaskProgress::default());
+        let ok = registry.spawn("ok", Arc::clone(&progress), async move {
+            progress.set_total(2);
+            progress.add_read(2);
+            progress.add_written(2);
+            Ok(())
+        });
+        let failed = registry.spawn("failed", Arc::new(TaskProgress::default()), async { Err(Error::UnknownError) });
+
+        let status = wait_for_task(&registry, ok).await;
+        assert_eq!(status.state, TaskState::Completed);
+        assert_eq!(status.written, 2);
+        let status = wait_for_task(&registry, failed).await;
+        assert_eq!(status.state, TaskState::Failed);
+        assert!(status.error.is_some());
+        assert!(registry.status(failed + 1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_panicked_task() {
+        let registry = Arc::new(TaskRegistry::default());
+        let id = registry.spawn("panic", Arc::new(TaskProgress::default()), async { panic!("Broken job") });
+        let status = wait_for_task(&registry, id).await;
+        assert_eq!(status.state, TaskState::Failed);
+        assert!(status.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_task() {
+        let registry = Arc::new(TaskRegistry::default());
+        let progress = Arc::new(TaskProgress::default());
+        let job_progress = Arc::clone(&progress);
+        let id = registry.spawn("cancel", progress, async move {
+            while !job_progress.is_cancelled() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Err(Error::TaskCancelled)
+        });
+        assert_eq!(registry.cancel(id).unwrap().state, TaskState::Running);
+        assert_eq!(wait_for_task(&registry, id).await.state, TaskState::Cancelled);
+        assert!(registry.cancel(id + 1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_finished_tasks_are_forgotten() {
+        let registry = Arc::new(TaskRegistry::with_max_finished(2));
+        let progress = Arc::new(TaskProgress::default());
+        let job_progress = Arc::clone(&progress);
+        let running = registry.spawn("running", progress, async move {
+            while !job_progress.is_cancelled() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        });
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let id = registry.spawn("ok", Arc::new(TaskProgress::default()), async { Ok(()) });
+            wait_for_task(&registry, id).await;
+            ids.push(id);
+        }
+
+        // The oldest is forgotten just after the newest is marked as finished
+        for _ in 0..500 {
+            if registry.status(ids[0]).is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(registry.status(ids[0]).is_none());
+        assert!(registry.status(ids[1]).is_some());
+        assert!(registry.status(ids[2]).is_some());
+        assert!(registry.status(running).is_some());
+        registry.cancel(running);
+    }
+}
@@ -1,8 +1,18 @@
-use http::{Response, StatusCode};
+use std::io;
+
+use async_compression::tokio::bufread::{GzipDecoder, GzipEncoder, ZstdDecoder, ZstdEncoder};
+use futures::TryStreamExt;
+use http::header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY};
+use http::{HeaderMap, Response, StatusCode};
 use hyper::Body;
 use serde::Serialize;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::{ReaderStream, StreamReader};
 use toshi_types::{Error, ErrorResponse};
 
+/// Response bodies smaller than this are sent as is, compressing them isn't worth the time
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
 pub fn with_body<T>(body: T) -> Response<Body>
 where
     T: Serialize,
@@ -29,10 +39,131 @@ pub async fn not_found() -> Result<Response<Body>, hyper::Error> {
     Ok(empty_with_code(StatusCode::NOT_FOUND))
 }
 
+/// Wrap a request body so it's decompressed as it's read according to its `Content-Encoding`
+pub fn decode_body(headers: &HeaderMap, body: Body) -> Result<Body, Error> {
+    let encoding = match headers.get(CONTENT_ENCODING).map(|v| v.to_str()) {
+        None => return Ok(body),
+        Some(Ok(encoding)) => encoding.trim().to_ascii_lowercase(),
+        Some(Err(e)) => return Err(Error::QueryError(format!("Invalid Content-Encoding: {}", e))),
+    };
+    let reader = |body: Body| StreamReader::new(body.map_err(io::Error::other));
+    match encoding.as_str() {
+        "" | "identity" => Ok(body),
+        "gzip" | "x-gzip" => {
+            let mut decoder = GzipDecoder::new(reader(body));
+            decoder.multiple_members(true);
+            Ok(Body::wrap_stream(ReaderStream::new(decoder)))
+        }
+        "zstd" => Ok(Body::wrap_stream(ReaderStream::new(ZstdDecoder::new(reader(body))))),
+        other => Err(Error::QueryError(format!("Unsupported Content-Encoding: {}", other))),
+    }
+}
+
+/// The encoding out of an `Accept-Encoding` header responses are compressed with, zstd is preferred when the
+/// client weighs it the same as gzip
+pub fn preferred_encoding(accept: &str) -> Option<&'static str> {
+    let mut best: Option<(&'static str, f32)> = None;
+    for entry in accept.split(',') {
+        let mut params = entry.split(';');
+        let name = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let quality = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let encoding = match name.as_str() {
+            "zstd" => "zstd",
+            "gzip" | "x-gzip" | "*" => "gzip",
+            _ => continue,
+        };
+        let better = match best {
+            Some((current, q)) => quality > q || (quality == q && encoding == "zstd" && current != "zstd"),
+            None => true,
+        };
+        if quality > 0.0 && better {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Compress a response built by [`with_body`] when the request's `Accept-Encoding` allows it and the body is large
+/// enough to be worth compressing
+pub async fn encode_response(resp: Response<Body>, request_headers: &HeaderMap) -> Result<Response<Body>, hyper::Error> {
+    let encoding = request_headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .and_then(preferred_encoding);
+    let encoding = match encoding {
+        Some(encoding) if !resp.headers().contains_key(CONTENT_ENCODING) => encoding,
+        _ => return Ok(resp),
+    };
+    let (mut parts, body) = resp.into_parts();
+    let bytes = hyper::body::to_bytes(body).await?;
+    if bytes.len() < MIN_COMPRESSED_SIZE {
+        return Ok(Response::from_parts(parts, Body::from(bytes)));
+    }
+
+    let mut compressed = Vec::with_capacity(bytes.len() / 4);
+    let result = match encoding {
+        "zstd" => ZstdEncoder::new(&bytes[..]).read_to_end(&mut compressed).await,
+        _ => GzipEncoder::new(&bytes[..]).read_to_end(&mut compressed).await,
+    };
+    if result.is_err() {
+        return Ok(Response::from_parts(parts, Body::from(bytes)));
+    }
+    parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    parts.headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    parts.headers.remove(CONTENT_LENGTH);
+    Ok(Response::from_parts(parts, Body::from(compressed)))
+}
+
 pub fn parse_path(path: &str) -> Vec<&str> {
     path.trim_matches('/').split('/').filter(|s| !s.is_empty()).collect()
 }
 
+/// Match a name against a pattern where `*` stands in for any number of characters
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    if !name.starts_with(first) {
+        return false;
+    }
+    let mut rest = &name[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    if let Some((last, middle)) = parts.split_last() {
+        for part in middle {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+        rest.len() >= last.len() && rest.ends_with(last)
+    } else {
+        rest.is_empty()
+    }
+}
+
+/// Apply a JSON merge patch (RFC 7396) to a value, nulls in the patch remove the matching keys
+pub fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    match patch {
+        serde_json::Value::Object(fields) => {
+            if !target.is_object() {
+                *target = serde_json::Value::Object(serde_json::Map::new());
+            }
+            if let Some(target_fields) = target.as_object_mut() {
+                for (key, value) in fields {
+                    if value.is_null() {
+                        target_fields.remove(key);
+                    } else {
+                        merge_patch(target_fields.entry(key.as_str()).or_insert(serde_json::Value::Null), value);
+                    }
+                }
+            }
+        }
+        _ => *target = patch.clone(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,4 +185,58 @@ mod tests {
         assert_eq!(parsed_two[0], "path");
         assert_eq!(parsed_two[1], "two");
     }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("logs-*", "logs-2021"));
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("logs-*-app", "logs-2021-app"));
+        assert!(wildcard_match("exact", "exact"));
+        assert!(!wildcard_match("exact", "exactly"));
+        assert!(!wildcard_match("logs-*", "metrics-2021"));
+        assert!(!wildcard_match("logs-*-app", "logs-2021-db"));
+        assert!(!wildcard_match("a*a", "a"));
+    }
+
+    #[test]
+    fn test_preferred_encoding() {
+        assert_eq!(preferred_encoding("gzip, deflate, br"), Some("gzip"));
+        assert_eq!(preferred_encoding("gzip, zstd"), Some("zstd"));
+        assert_eq!(preferred_encoding("zstd;q=0.5, gzip;q=0.8"), Some("gzip"));
+        assert_eq!(preferred_encoding("*"), Some("gzip"));
+        assert_eq!(preferred_encoding("gzip;q=0, br"), None);
+        assert_eq!(preferred_encoding("identity"), None);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_bodies() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let json = serde_json::to_vec(&vec!["compress me"; 200])?;
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        let resp = encode_response(with_body(vec!["compress me"; 200]), &request_headers).await?;
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+
+        let (parts, body) = resp.into_parts();
+        let decoded = decode_body(&parts.headers, body)?;
+        assert_eq!(hyper::body::to_bytes(decoded).await?, json);
+
+        let small = encode_response(with_body("small"), &request_headers).await?;
+        assert!(!small.headers().contains_key(CONTENT_ENCODING));
+
+        let mut unsupported = HeaderMap::new();
+        unsupported.insert(CONTENT_ENCODING, HeaderValue::from_static("compress"));
+        assert!(decode_body(&unsupported, Body::empty()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut target =
+            serde_json::json!({"title": "Goodbye!", "author": {"given": "John", "family": "Doe"}, "tags": ["example", "sample"]});
+        let patch = serde_json::json!({"title": "Hello!", "phone": "+01-123-456-7890", "author": {"family": null}, "tags": ["example"]});
+        merge_patch(&mut target, &patch);
+        let expected =
+            serde_json::json!({"title": "Hello!", "author": {"given": "John"}, "tags": ["example"], "phone": "+01-123-456-7890"});
+        assert_eq!(target, expected);
+    }
 }
@@ -1,5 +1,4 @@
 use std::net::SocketAddr;
-use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
 use hyper::body::to_bytes;
@@ -21,7 +20,7 @@ async fn test_client() -> Result<(), BoxErr> {
     };
 
     let catalog = IndexCatalog::new(settings)?;
-    let router = Router::new(Arc::new(catalog), Arc::new(AtomicBool::new(false)));
+    let router = Router::new(Arc::new(catalog));
 
     tokio::spawn(router.router_with_catalog(addr));
 
@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::iter::Sum;
 use std::ops::Add;
 
@@ -5,7 +7,9 @@ use serde::{Deserialize, Serialize};
 use tantivy::space_usage::SearcherSpaceUsage;
 use tantivy::IndexMeta;
 
-use crate::query::KeyValue;
+use crate::error::ErrorResponse;
+use crate::query::{KeyValue, SortOrder};
+use crate::server::{CommitPolicy, IndexConfig};
 
 /// A single document returned from a Tantivy Index
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -14,12 +18,66 @@ pub struct ScoredDoc<D: Clone> {
     pub score: Option<f32>,
     /// The actual document
     pub doc: D,
+    /// The index this document came from, only set when searching multiple indexes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    /// The value of the field the results were sorted by, only set when sorting by a field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_value: Option<SortValue>,
+}
+
+/// The value of a fast field that results were sorted by, dates are microseconds since the Unix epoch
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum SortValue {
+    /// An unsigned value
+    U64(u64),
+    /// A signed value or date
+    I64(i64),
+    /// A floating point value
+    F64(f64),
+}
+
+impl SortValue {
+    fn as_f64(self) -> f64 {
+        match self {
+            SortValue::U64(v) => v as f64,
+            SortValue::I64(v) => v as f64,
+            SortValue::F64(v) => v,
+        }
+    }
+
+    /// Compare two values exactly when they have the same type, values of different types are compared as floats
+    fn compare(&self, other: &SortValue) -> Ordering {
+        match (self, other) {
+            (SortValue::U64(a), SortValue::U64(b)) => a.cmp(b),
+            (SortValue::I64(a), SortValue::I64(b)) => a.cmp(b),
+            (a, b) => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+        }
+    }
 }
 
 impl<D: Clone> ScoredDoc<D> {
     /// Constructor for a new ScoredDoc
     pub fn new(score: Option<f32>, doc: D) -> Self {
-        Self { score, doc }
+        Self {
+            score,
+            doc,
+            index: None,
+            sort_value: None,
+        }
+    }
+
+    /// Record the value of the field this document was sorted by
+    pub fn with_sort_value(mut self, value: SortValue) -> Self {
+        self.sort_value = Some(value);
+        self
+    }
+
+    /// Tag this document with the index it was found in
+    pub fn with_index<I: ToString>(mut self, index: I) -> Self {
+        self.index = Some(index.to_string());
+        self
     }
 }
 
@@ -81,6 +139,187 @@ impl<D: Clone> SearchResults<D> {
             facets,
         }
     }
+
+    /// Tag every returned document with the index it came from
+    pub fn with_index(mut self, index: &str) -> Self {
+        self.docs = self.docs.into_iter().map(|d| d.with_index(index)).collect();
+        self
+    }
+
+    /// Merge the results of several searches into one, ordering documents by their score (or by their sort
+    /// value in the given order when sorting by a field) and keeping only the top `limit` of them. Facet counts
+    /// for the same facet are summed together.
+    pub fn merge<I>(results: I, limit: usize, order: SortOrder) -> Self
+    where
+        I: IntoIterator<Item = SearchResults<D>>,
+    {
+        let mut docs = Vec::new();
+        let mut facets: BTreeMap<String, u64> = BTreeMap::new();
+        for mut result in results {
+            docs.append(&mut result.docs);
+            for kv in result.facets {
+                *facets.entry(kv.field).or_insert(0) += kv.value;
+            }
+        }
+        docs.sort_by(|a, b| match (&a.sort_value, &b.sort_value) {
+            (Some(a), Some(b)) if order == SortOrder::Asc => a.compare(b),
+            (Some(a), Some(b)) => b.compare(a),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => match (a.score, b.score) {
+                (Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+        });
+        docs.truncate(limit);
+        let facets = facets.into_iter().map(|(f, c)| KeyValue::new(f, c)).collect();
+        Self::with_facets(docs, facets)
+    }
+}
+
+/// The number of documents matching a query, returned from the _count route
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct DocCount {
+    /// The number of matching documents
+    pub count: u64,
+}
+
+/// A candidate correction for a term
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Suggestion {
+    /// The suggested term
+    pub term: String,
+    /// The edit distance between the suggestion and the original term
+    pub distance: u8,
+    /// The number of documents containing the suggested term
+    pub doc_freq: u64,
+}
+
+/// A term starting with the prefix given to the _complete route
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Completion {
+    /// The completed term
+    pub term: String,
+    /// The number of documents containing the term
+    pub doc_freq: u64,
+}
+
+/// The suggestions for a single term of the text given to the _suggest route
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TermSuggestions {
+    /// The term as produced by the field's tokenizer
+    pub text: String,
+    /// The candidate corrections, closest and most frequent first
+    pub options: Vec<Suggestion>,
+}
+
+/// The suggestions for one field of a _suggest request
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldSuggestions {
+    /// The text with every term that doesn't exist in the index replaced by its best suggestion, this
+    /// is only present when at least one term was corrected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub did_you_mean: Option<String>,
+    /// The suggestions for each term of the text
+    pub terms: Vec<TermSuggestions>,
+}
+
+/// A single entry in the response of a _msearch request, each search either returns its results
+/// or the error that prevented it from running
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum MultiSearchResult<D: Clone> {
+    /// The search ran successfully
+    Results(SearchResults<D>),
+    /// The search failed
+    Error(ErrorResponse),
+}
+
+/// The effective settings of an index as returned by the _settings route
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SettingsResponse {
+    /// The memory in bytes given to the index's writer
+    pub writer_memory: usize,
+    /// The merge policy the index's writer uses
+    pub merge_policy: String,
+    /// When the index's reader picks up new changes
+    pub reload_policy: String,
+    /// The tokenizer each indexed text field uses
+    pub tokenizers: BTreeMap<String, String>,
+    /// The Toshi specific config the index was created with
+    pub config: IndexConfig,
+}
+
+/// A line of a bulk request that could not be indexed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BulkLineError {
+    /// The line number in the request body, starting at 1
+    pub line: usize,
+    /// Why the line was rejected
+    pub message: String,
+}
+
+/// The summary of a bulk request
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BulkResponse {
+    /// The number of documents that were indexed
+    pub accepted: u64,
+    /// The number of lines that were left out
+    pub rejected: u64,
+    /// The lines that were left out and why
+    pub errors: Vec<BulkLineError>,
+}
+
+/// The outcome of a single action in a mixed bulk request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BulkItemResult {
+    /// The name of the action
+    pub action: String,
+    /// The index the action applied to
+    pub index: String,
+    /// The id of the document the action applied to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The HTTP status the action would have gotten as a request of its own
+    pub status: u16,
+    /// Why the action failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The response to a mixed bulk request, with a result for each action in the order they were given
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BulkActionsResponse {
+    /// Whether any of the actions failed
+    pub errors: bool,
+    /// The result of each action
+    pub items: Vec<BulkItemResult>,
+}
+
+/// Statistics about the documents deleted from an index
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct DeletedDocs {
+    /// Documents deleted through this index since it was opened, including deletes not yet committed
+    pub total: u64,
+    /// Deleted documents that segments still hold on to until they are merged away
+    pub in_segments: u64,
+}
+
+/// The commit policy of an index along with the writes waiting on a commit
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct CommitStatus {
+    /// The policy the commit watcher follows for this index
+    pub policy: CommitPolicy,
+    /// Documents written since the last commit
+    pub pending_docs: u64,
+    /// Bytes of documents written since the last commit, counted as they were sent
+    pub pending_bytes: u64,
+    /// When the index was last committed in milliseconds since the Unix epoch, none when it hasn't been
+    /// committed since it was opened
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_commit: Option<u64>,
 }
 
 /// A response gotten from the _summary route for an index
@@ -89,18 +328,39 @@ pub struct SummaryResponse {
     summaries: IndexMeta,
     #[serde(skip_serializing_if = "Option::is_none")]
     segment_sizes: Option<SearcherSpaceUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_docs: Option<DeletedDocs>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commit: Option<CommitStatus>,
 }
 
 impl SummaryResponse {
     /// Constructor for a new summary response
     pub fn new(summaries: IndexMeta, segment_sizes: Option<SearcherSpaceUsage>) -> Self {
-        Self { summaries, segment_sizes }
+        Self {
+            summaries,
+            segment_sizes,
+            deleted_docs: None,
+            commit: None,
+        }
+    }
+
+    /// Include the index's deleted document statistics in the summary
+    pub fn with_deleted_docs(mut self, deleted_docs: DeletedDocs) -> Self {
+        self.deleted_docs = Some(deleted_docs);
+        self
+    }
+
+    /// Include the index's commit policy and pending writes in the summary
+    pub fn with_commit_status(mut self, commit: CommitStatus) -> Self {
+        self.commit = Some(commit);
+        self
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ScoredDoc, SearchResults};
+    use crate::{ScoredDoc, SearchResults, SortOrder, SortValue};
     use std::collections::BTreeMap;
 
     #[test]
@@ -126,4 +386,42 @@ mod tests {
         assert_eq!(both.docs.len(), 2);
         assert_eq!(both.hits, 2);
     }
+
+    #[test]
+    fn test_merge() {
+        let doc = BTreeMap::<String, String>::new();
+        let first = SearchResults::new(vec![ScoredDoc::new(Some(0.5), doc.clone()), ScoredDoc::new(Some(0.1), doc.clone())]);
+        let second = SearchResults::new(vec![ScoredDoc::new(Some(2.0), doc.clone()), ScoredDoc::new(Some(0.3), doc)]);
+        let merged = SearchResults::merge(vec![first.with_index("first"), second.with_index("second")], 3, SortOrder::Desc);
+
+        assert_eq!(merged.hits, 3);
+        let scores: Vec<f32> = merged.docs.iter().filter_map(|d| d.score).collect();
+        assert_eq!(scores, vec![2.0, 0.5, 0.3]);
+        assert_eq!(merged.docs[0].index.as_deref(), Some("second"));
+        assert_eq!(merged.docs[1].index.as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn test_merge_sort_values() {
+        let doc = BTreeMap::<String, String>::new();
+        let sorted = |values: &[u64]| {
+            SearchResults::new(
+                values
+                    .iter()
+                    .map(|v| ScoredDoc::new(Some(*v as f32), doc.clone()).with_sort_value(SortValue::U64(*v)))
+                    .collect(),
+            )
+        };
+        // Timestamps a microsecond apart are the same once cast to f32
+        let base = 1_600_000_000_000_000;
+        let values = |merged: SearchResults<BTreeMap<String, String>>| -> Vec<SortValue> {
+            merged.docs.iter().filter_map(|d| d.sort_value).collect()
+        };
+
+        let desc = SearchResults::merge(vec![sorted(&[base + 3, base + 1]), sorted(&[base + 2])], 2, SortOrder::Desc);
+        assert_eq!(values(desc), vec![SortValue::U64(base + 3), SortValue::U64(base + 2)]);
+
+        let asc = SearchResults::merge(vec![sorted(&[base + 1, base + 3]), sorted(&[base + 2])], 2, SortOrder::Asc);
+        assert_eq!(values(asc), vec![SortValue::U64(base + 1), SortValue::U64(base + 2)]);
+    }
 }
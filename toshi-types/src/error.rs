@@ -12,7 +12,7 @@ use tantivy::TantivyError;
 use thiserror::Error;
 
 /// The type returned when an error occurs with a query
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ErrorResponse {
     /// The human-readable message given back
     pub message: String,
@@ -72,6 +72,45 @@ pub enum Error {
     /// When attempting to create an index that already exists
     #[error("Index: '{0}' already exists")]
     AlreadyExists(String),
+    /// When an index is created with a config that doesn't fit its schema
+    #[error("Invalid index config: '{0}'")]
+    InvalidConfig(String),
+    /// When looking up documents by id in an index that has no primary key
+    #[error("Index: '{0}' has no primary key")]
+    MissingPrimaryKey(String),
+    /// When a document looked up by id does not exist
+    #[error("Document: '{0}' does not exist")]
+    UnknownDocument(String),
+    /// When a document is updated while writes to it wait on a commit, which the stored document doesn't have yet
+    #[error("Document: '{0}' has writes waiting on a commit")]
+    DocumentPending(String),
+    /// When a stored document can't be rebuilt because some of its indexed fields were never stored
+    #[error("Fields: '{0}' are indexed but not stored and must be given to update the document")]
+    UnstoredFields(String),
+    /// When an index is removed while a bulk insert or background task is still writing to it
+    #[error("Index: '{0}' has writes in progress")]
+    IndexInUse(String),
+    /// When looking up a background task that does not exist
+    #[error("Task: '{0}' does not exist")]
+    UnknownTask(String),
+    /// When a background task stops early because it was cancelled
+    #[error("Task was cancelled")]
+    TaskCancelled,
+    /// When an alias that does not exist is changed
+    #[error("Alias: '{0}' does not exist")]
+    UnknownAlias(String),
+    /// When an alias that points at several indexes is used where a single index is needed
+    #[error("Alias: '{0}' points at more than one index")]
+    AmbiguousAlias(String),
+    /// When looking up an index template that does not exist
+    #[error("Template: '{0}' does not exist")]
+    UnknownTemplate(String),
+    /// When looking up an ingest pipeline that does not exist
+    #[error("Pipeline: '{0}' does not exist")]
+    UnknownPipeline(String),
+    /// When a processor in an ingest pipeline can't handle a document
+    #[error("Error in pipeline: '{0}'")]
+    ProcessorError(String),
     /// When an invalid log config is provided
     #[error("Error Deserializing Error: '{0}'")]
     TomlError(toml::de::Error),
@@ -5,7 +5,8 @@
 //! looking for Toshi's protobuf types then you will want to look in the toshi-proto module
 //! of Toshi's source code.
 
-use std::sync::atomic::AtomicBool;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use hyper::{Body, Response};
@@ -15,13 +16,18 @@ use tantivy::space_usage::SearcherSpaceUsage;
 use tantivy::{Index, IndexWriter};
 use tokio::sync::Mutex;
 
-pub use client::{ScoredDoc, SearchResults, SummaryResponse};
+pub use client::{
+    BulkActionsResponse, BulkItemResult, BulkLineError, BulkResponse, CommitStatus, Completion, DeletedDocs, DocCount, FieldSuggestions,
+    MultiSearchResult, ScoredDoc, SearchResults, SettingsResponse, SortValue, Suggestion, SummaryResponse, TermSuggestions,
+};
 pub use error::{Error, ErrorResponse};
+pub use pipeline::{ConvertType, Pipeline, Processor};
 pub use query::{
     boolean::BoolQuery, facet::FacetQuery, fuzzy::FuzzyQuery, fuzzy::FuzzyTerm, phrase::PhraseQuery, phrase::TermPair, range::RangeQuery,
-    range::Ranges, regex::RegexQuery, term::ExactTerm, CreateQuery, FlatNamedDocument, KeyValue, Query, QueryOptions, Search,
+    range::Ranges, regex::RegexQuery, term::ExactTerm, CreateQuery, FlatNamedDocument, KeyValue, Query, QueryOptions, Search, SortOrder,
 };
 pub use server::*;
+pub use tasks::{TaskCreated, TaskProgress, TaskState, TaskStatus};
 
 /// Toshi client result type
 pub type Result<T> = std::result::Result<T, error::Error>;
@@ -35,14 +41,70 @@ mod error;
 /// Types related to Toshi's Query DSL
 mod query;
 
+/// Named pipelines of processors that transform documents before they are indexed
+mod pipeline;
+
 /// Types related to the POST bodies that Toshi accepts for requests
 mod server;
 
+/// Types used to track and report on long running background jobs
+mod tasks;
+
 /// Extra error conversions Toshi uses, if users want they can omit this feature to not pull in
 /// hyper and tonic dependencies
 #[cfg(feature = "extra-errors")]
 mod extra_errors;
 
+/// Held for as long as a bulk insert or a background task writes to an index, the index isn't auto committed or
+/// removed while any session on it is open. Sessions on an index are counted so several can run on it at once,
+/// unless one of them holds the index exclusively so it can roll back the writer without losing anyone else's writes.
+#[derive(Debug)]
+pub struct BulkSession {
+    sessions: Arc<AtomicUsize>,
+    exclusive: bool,
+}
+
+impl BulkSession {
+    /// Set in an index's session counter while a session holds the index exclusively
+    const EXCLUSIVE: usize = 1 << (usize::BITS - 1);
+
+    /// Open a session on the counter of an index's open sessions, or `None` if the index is held exclusively
+    pub fn open(sessions: &Arc<AtomicUsize>) -> Option<Self> {
+        sessions
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                Some(open + 1).filter(|_| open & Self::EXCLUSIVE == 0)
+            })
+            .ok()?;
+        Some(Self {
+            sessions: Arc::clone(sessions),
+            exclusive: false,
+        })
+    }
+
+    /// Open the only session on an index, or `None` if any other session is open
+    pub fn open_exclusive(sessions: &Arc<AtomicUsize>) -> Option<Self> {
+        sessions
+            .compare_exchange(0, Self::EXCLUSIVE | 1, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()?;
+        Some(Self {
+            sessions: Arc::clone(sessions),
+            exclusive: true,
+        })
+    }
+
+    /// Whether a session holds the index with this counter exclusively
+    pub fn is_held_exclusively(sessions: &AtomicUsize) -> bool {
+        sessions.load(Ordering::SeqCst) & Self::EXCLUSIVE != 0
+    }
+}
+
+impl Drop for BulkSession {
+    fn drop(&mut self) {
+        let held = if self.exclusive { Self::EXCLUSIVE | 1 } else { 1 };
+        self.sessions.fetch_sub(held, Ordering::SeqCst);
+    }
+}
+
 /// Defines an interface on how operations are done on indexes inside Toshi
 #[async_trait::async_trait]
 pub trait IndexHandle: Clone {
@@ -52,20 +114,72 @@ pub trait IndexHandle: Clone {
     fn get_index(&self) -> Index;
     /// Return index writer
     fn get_writer(&self) -> Arc<Mutex<IndexWriter>>;
+    /// Return the Toshi specific config of this index
+    fn get_config(&self) -> IndexConfig;
     /// Get size of an index
     fn get_space(&self) -> SearcherSpaceUsage;
+    /// Statistics about the documents deleted from this index
+    fn get_deleted_docs(&self) -> DeletedDocs;
+    /// The effective settings of this index
+    async fn get_settings(&self) -> SettingsResponse;
     /// The agreed upon raft commit ID this index is currently at.
     fn get_opstamp(&self) -> usize;
     /// Set that opstamp
     fn set_opstamp(&self, opstamp: usize);
     /// Commit the current index writes
     async fn commit(&self) -> Result<u64>;
+    /// Throw away every write made to this index since its last commit
+    async fn rollback(&self) -> Result<()>;
+    /// Count writes made to this index that wait on a commit. Called while the writer is still locked for the
+    /// write, so a commit can't reset the counts in between the write and its count.
+    fn add_pending(&self, docs: usize, bytes: usize);
+    /// The commit policy of this index and the writes waiting on a commit
+    fn get_commit_status(&self) -> CommitStatus;
+    /// Whether the index has pending writes its commit policy says are due to be committed, `default_interval` is
+    /// used when the policy doesn't have its own
+    fn commit_due(&self, default_interval: f32) -> bool;
+    /// Open a bulk session on this index, holding off auto commits until it's dropped. Fails while another session
+    /// holds the index exclusively.
+    fn begin_bulk(&self) -> Result<BulkSession>;
+    /// Open the only session on this index, during which no other session can be opened and no other writes made.
+    /// Fails while any other session is open.
+    fn begin_exclusive_bulk(&self) -> Result<BulkSession>;
+    /// Whether any bulk session is open on this index
+    fn in_bulk(&self) -> bool;
     /// Search for documents in this index
     async fn search_index(&self, search: Search) -> Result<SearchResults<FlatNamedDocument>>;
+    /// Count the documents in this index matching a query
+    async fn count(&self, query: Query) -> Result<DocCount>;
+    /// Suggest corrections for text from the terms of this index
+    async fn suggest(&self, request: SuggestRequest) -> Result<BTreeMap<String, FieldSuggestions>>;
+    /// Complete a prefix from the most frequent terms of a field
+    async fn complete(&self, request: CompleteRequest) -> Result<Vec<Completion>>;
     /// Add documents to this index
     async fn add_document(&self, doc: AddDocument<SerdeValue>) -> Result<()>;
+    /// Look up a single document by its primary key
+    async fn get_document(&self, id: &str) -> Result<Option<FlatNamedDocument>>;
+    /// Add a document with the given primary key, replacing the existing document with that key
+    async fn upsert_document(&self, id: &str, doc: AddDocument<SerdeValue>) -> Result<()>;
+    /// Apply a JSON merge patch to the stored fields of the document with the given primary key and reindex it,
+    /// returning the document as it was written. Fails while writes to the document wait on a commit, since the
+    /// stored document doesn't have them yet.
+    async fn update_document(&self, id: &str, patch: AddDocument<SerdeValue>) -> Result<SerdeValue>;
     /// Delete terms/documents from this index
     async fn delete_term(&self, term: DeleteDoc) -> Result<DocsAffected>;
+    /// Delete the document with the given primary key
+    async fn delete_document(&self, id: &str, options: Option<IndexOptions>) -> Result<DocsAffected>;
+    /// Delete every document matching a query, returning how many matched when the delete was made
+    async fn delete_by_query(&self, request: DeleteByQuery) -> Result<DocsAffected>;
+    /// Apply field assignments to every document matching a query and reindex them by their primary key, reporting
+    /// along the way to the task's progress. Each batch is written once it's parsed, so a document that fails to
+    /// parse or a cancel stops the update between batches, leaving the documents already rewritten in place. Fails
+    /// with `Error::TaskCancelled` when cancelled, and with `Error::MissingPrimaryKey` on an index without a primary key.
+    async fn update_by_query(&self, request: UpdateByQuery, progress: Arc<TaskProgress>) -> Result<()>;
+    /// Copy the stored fields of documents in this index into another one, reporting along the way to the
+    /// task's progress. Fields that are not stored can't be copied. Each batch is written to the destination once
+    /// it's read, and a cancel stops the copy between batches with `Error::TaskCancelled`, leaving the documents
+    /// already written in place.
+    async fn reindex(&self, dest: Self, request: ReindexRequest, progress: Arc<TaskProgress>) -> Result<()>;
 }
 
 /// Defines the interface for obtaining a handle from a catalog to an index
@@ -79,13 +193,47 @@ pub trait Catalog: Send + Sync + 'static {
     /// Return the entire collection of handles
     fn get_collection(&self) -> &dashmap::DashMap<String, Self::Handle>;
     /// Add a local index to the catalog
-    async fn add_index(&self, name: &str, schema: Schema) -> Result<()>;
+    async fn add_index(&self, name: &str, schema: Schema) -> Result<()> {
+        self.add_index_with_config(name, schema, IndexConfig::default()).await
+    }
+    /// Add a local index with a Toshi specific config to the catalog
+    async fn add_index_with_config(&self, name: &str, schema: Schema, config: IndexConfig) -> Result<()>;
+    /// Remove a local index from the catalog, throwing away any uncommitted changes and its data on disk. An index
+    /// can't be removed while a bulk insert or background task is writing to it, and other requests still using it
+    /// are given a few seconds to finish before the removal gives up.
+    async fn remove_index(&self, name: &str) -> Result<()>;
     /// Return a list of index names
     async fn list_indexes(&self) -> Vec<String>;
     /// Return a handle to a single index
     fn get_index(&self, name: &str) -> Result<Self::Handle>;
     /// Determine if an index exists locally
     fn exists(&self, index: &str) -> bool;
+    /// Expand a comma separated list of index names and `*` wildcard patterns into the names of
+    /// the indexes they refer to
+    fn resolve_indexes(&self, pattern: &str) -> Result<Vec<String>>;
+    /// Whether a name refers to more than one index, either as a comma separated list, a wildcard pattern or
+    /// an alias of several indexes
+    fn is_multi_index(&self, name: &str) -> bool {
+        name.contains(',') || name.contains('*') || self.get_aliases().get(name).is_some_and(|i| i.len() > 1)
+    }
+    /// Every alias along with the indexes it points at
+    fn get_aliases(&self) -> BTreeMap<String, Vec<String>>;
+    /// Apply a set of alias changes together, if any of them fail none are applied
+    fn update_aliases(&self, actions: Vec<AliasAction>) -> Result<()>;
+    /// Every template along with its name
+    fn get_templates(&self) -> BTreeMap<String, IndexTemplate>;
+    /// Add or replace a template
+    fn put_template(&self, name: &str, template: IndexTemplate) -> Result<()>;
+    /// Every ingest pipeline along with its name
+    fn get_pipelines(&self) -> BTreeMap<String, Pipeline>;
+    /// Look up a single ingest pipeline
+    fn get_pipeline(&self, name: &str) -> Result<Pipeline> {
+        self.get_pipelines().remove(name).ok_or_else(|| Error::UnknownPipeline(name.into()))
+    }
+    /// Add or replace an ingest pipeline
+    fn put_pipeline(&self, name: &str, pipeline: Pipeline) -> Result<()>;
+    /// Return a handle to a single index, creating the index from a matching template when it does not exist
+    async fn get_or_create_index(&self, name: &str) -> Result<Self::Handle>;
 }
 
 #[allow(missing_docs)]
@@ -104,7 +252,6 @@ where
 
     async fn bulk_insert(
         catalog: Arc<C>,
-        watcher: Arc<AtomicBool>,
         mut body: Body,
         index: &str,
         num_threads: usize,
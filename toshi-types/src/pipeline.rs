@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::{format_description, Date, OffsetDateTime, PrimitiveDateTime};
+
+use crate::{Error, Result};
+
+/// A named list of processors documents are run through before they are indexed
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Pipeline {
+    /// What the pipeline is for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The processors in the order they are run
+    pub processors: Vec<Processor>,
+}
+
+/// The types a field can be converted to by the convert processor
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConvertType {
+    /// A signed integer
+    Integer,
+    /// A floating point number
+    Float,
+    /// true or false, parsed case insensitively from strings
+    Boolean,
+    /// The text of the value
+    String,
+}
+
+/// A single transform applied to a document's top level fields. Processors other than set_default do nothing when
+/// their field is missing
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Processor {
+    /// Move a field to a new name, replacing whatever was there
+    Rename {
+        /// The field to move
+        field: String,
+        /// The name to move it to
+        target_field: String,
+    },
+    /// Remove a field
+    Remove {
+        /// The field to remove
+        field: String,
+    },
+    /// Give a field a value when it's missing or null
+    SetDefault {
+        /// The field to set
+        field: String,
+        /// The value to give it
+        value: Value,
+    },
+    /// Lowercase a string, or each string in an array
+    Lowercase {
+        /// The field to lowercase
+        field: String,
+    },
+    /// Split a string into an array of strings
+    Split {
+        /// The field to split
+        field: String,
+        /// What to split the string on
+        separator: String,
+    },
+    /// Parse a date out of a field and replace it with the date in RFC 3339 form, which is what date fields expect
+    Date {
+        /// The field to parse
+        field: String,
+        /// The formats to try in order, each is `rfc3339`, `rfc2822`, `unix` for seconds, `unix_ms` for
+        /// milliseconds or a format description such as `[year]-[month]-[day] [hour]:[minute]:[second]`, which
+        /// is read as UTC when it has no offset
+        formats: Vec<String>,
+        /// Where to put the parsed date, defaults to the field itself
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target_field: Option<String>,
+    },
+    /// Convert a value, or each value in an array, to another type
+    Convert {
+        /// The field to convert
+        field: String,
+        /// The type to convert to
+        to: ConvertType,
+    },
+}
+
+impl Pipeline {
+    /// Check the pipeline can be run, for now that every date format is one that can be parsed
+    pub fn validate(&self) -> Result<()> {
+        for processor in &self.processors {
+            if let Processor::Date { formats, .. } = processor {
+                if formats.is_empty() {
+                    return Err(Error::InvalidConfig("The date processor needs at least one format".into()));
+                }
+                for format in formats.iter().filter(|f| !WELL_KNOWN_FORMATS.contains(&f.as_str())) {
+                    format_description::parse(format).map_err(|e| Error::InvalidConfig(format!("Date format '{}': {}", format, e)))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a document through each processor in turn
+    pub fn run(&self, document: &mut Value) -> Result<()> {
+        let fields = document
+            .as_object_mut()
+            .ok_or_else(|| Error::ProcessorError("Documents given to a pipeline must be JSON objects".into()))?;
+        self.processors.iter().try_for_each(|p| p.run(fields))
+    }
+}
+
+const WELL_KNOWN_FORMATS: [&str; 4] = ["rfc3339", "rfc2822", "unix", "unix_ms"];
+
+impl Processor {
+    fn run(&self, fields: &mut Map<String, Value>) -> Result<()> {
+        match self {
+            Processor::Rename { field, target_field } => {
+                if let Some(value) = fields.remove(field) {
+                    fields.insert(target_field.clone(), value);
+                }
+            }
+            Processor::Remove { field } => {
+                fields.remove(field);
+            }
+            Processor::SetDefault { field, value } => {
+                let existing = fields.entry(field.as_str()).or_insert(Value::Null);
+                if existing.is_null() {
+                    *existing = value.clone();
+                }
+            }
+            Processor::Lowercase { field } => {
+                if let Some(value) = fields.get_mut(field) {
+                    each_value(value, |v| match v {
+                        Value::String(s) => Ok(Value::String(s.to_lowercase())),
+                        other => Err(Error::ProcessorError(format!("Can't lowercase '{}' in field {}", other, field))),
+                    })?;
+                }
+            }
+            Processor::Split { field, separator } => {
+                if let Some(value) = fields.get_mut(field) {
+                    match value {
+                        Value::String(s) => {
+                            let parts = s.split(separator.as_str()).map(|p| Value::String(p.into())).collect();
+                            *value = parts;
+                        }
+                        Value::Array(_) => {}
+                        other => return Err(Error::ProcessorError(format!("Can't split '{}' in field {}", other, field))),
+                    }
+                }
+            }
+            Processor::Date {
+                field,
+                formats,
+                target_field,
+            } => {
+                if let Some(value) = fields.get(field) {
+                    let text = match value {
+                        Value::String(s) => s.clone(),
+                        Value::Number(n) => n.to_string(),
+                        other => {
+                            return Err(Error::ProcessorError(format!(
+                                "Can't parse a date from '{}' in field {}",
+                                other, field
+                            )))
+                        }
+                    };
+                    let date = formats
+                        .iter()
+                        .find_map(|f| parse_date(&text, f))
+                        .ok_or_else(|| Error::ProcessorError(format!("'{}' in field {} matches none of the date formats", text, field)))?;
+                    let date = date
+                        .format(&Rfc3339)
+                        .map_err(|e| Error::ProcessorError(format!("Can't format date in field {}: {}", field, e)))?;
+                    fields.insert(target_field.as_ref().unwrap_or(field).clone(), Value::String(date));
+                }
+            }
+            Processor::Convert { field, to } => {
+                if let Some(value) = fields.get_mut(field) {
+                    each_value(value, |v| {
+                        convert(v, *to)
+                            .ok_or_else(|| Error::ProcessorError(format!("Can't convert '{}' in field {} to {:?}", v, field, to)))
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply a change to a value, or to each value when it's an array
+fn each_value<F>(value: &mut Value, f: F) -> Result<()>
+where
+    F: Fn(&Value) -> Result<Value>,
+{
+    match value {
+        Value::Array(values) => values.iter_mut().try_for_each(|v| f(v).map(|new| *v = new)),
+        v => f(v).map(|new| *v = new),
+    }
+}
+
+fn convert(value: &Value, to: ConvertType) -> Option<Value> {
+    match (to, value) {
+        (ConvertType::String, Value::String(_)) => Some(value.clone()),
+        (ConvertType::String, v) => Some(Value::String(v.to_string())),
+        (ConvertType::Integer, Value::Number(n)) => n.as_i64().or_else(|| n.as_f64().map(|f| f as i64)).map(Value::from),
+        (ConvertType::Integer, Value::String(s)) => s.trim().parse::<i64>().ok().map(Value::from),
+        (ConvertType::Integer, Value::Bool(b)) => Some(Value::from(*b as i64)),
+        (ConvertType::Float, Value::Number(n)) => n.as_f64().map(Value::from),
+        (ConvertType::Float, Value::String(s)) => s.trim().parse::<f64>().ok().map(Value::from),
+        (ConvertType::Boolean, Value::Bool(_)) => Some(value.clone()),
+        (ConvertType::Boolean, Value::String(s)) => s.trim().to_lowercase().parse::<bool>().ok().map(Value::from),
+        (ConvertType::Boolean, Value::Number(n)) => n.as_i64().map(|i| Value::from(i != 0)),
+        _ => None,
+    }
+}
+
+fn parse_date(text: &str, format: &str) -> Option<OffsetDateTime> {
+    match format {
+        "rfc3339" => OffsetDateTime::parse(text, &Rfc3339).ok(),
+        "rfc2822" => OffsetDateTime::parse(text, &Rfc2822).ok(),
+        "unix" => text.parse::<i64>().ok().and_then(|s| OffsetDateTime::from_unix_timestamp(s).ok()),
+        "unix_ms" => text
+            .parse::<i128>()
+            .ok()
+            .and_then(|ms| OffsetDateTime::from_unix_timestamp_nanos(ms * 1_000_000).ok()),
+        custom => {
+            let description = format_description::parse(custom).ok()?;
+            OffsetDateTime::parse(text, &description)
+                .ok()
+                .or_else(|| PrimitiveDateTime::parse(text, &description).ok().map(|d| d.assume_utc()))
+                .or_else(|| Date::parse(text, &description).ok().map(|d| d.midnight().assume_utc()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_pipeline() -> Result<()> {
+        let pipeline: Pipeline = serde_json::from_value(json!({
+            "processors": [
+                {"rename": {"field": "Name", "target_field": "name"}},
+                {"remove": {"field": "internal"}},
+                {"set_default": {"field": "status", "value": "active"}},
+                {"lowercase": {"field": "name"}},
+                {"split": {"field": "tags", "separator": ","}},
+                {"date": {"field": "created", "formats": ["rfc3339", "[year]-[month]-[day]"]}},
+                {"convert": {"field": "count", "to": "integer"}}
+            ]
+        }))?;
+        pipeline.validate()?;
+
+        let mut doc = json!({"Name": "Toshi", "internal": true, "tags": "a,b", "created": "2021-03-04", "count": "12"});
+        pipeline.run(&mut doc)?;
+        let expected = json!({
+            "name": "toshi",
+            "status": "active",
+            "tags": ["a", "b"],
+            "created": "2021-03-04T00:00:00Z",
+            "count": 12
+        });
+        assert_eq!(doc, expected);
+
+        let mut bad = json!({"count": "twelve"});
+        assert!(pipeline.run(&mut bad).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_date_format() {
+        let pipeline = Pipeline {
+            description: None,
+            processors: vec![Processor::Date {
+                field: "created".into(),
+                formats: vec!["[year]-[nonsense]".into()],
+                target_field: None,
+            }],
+        };
+        assert!(pipeline.validate().is_err());
+    }
+}
@@ -115,6 +115,20 @@ pub struct Search {
     /// Field to sort results by
     #[serde(default)]
     pub sort_by: Option<String>,
+    /// The direction results are sorted by `sort_by` in
+    #[serde(default)]
+    pub sort_order: SortOrder,
+}
+
+/// The direction results sorted by a field are returned in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Smallest values first
+    Asc,
+    /// Largest values first
+    #[default]
+    Desc,
 }
 
 impl Search {
@@ -125,6 +139,7 @@ impl Search {
             facets,
             limit,
             sort_by,
+            sort_order: SortOrder::default(),
         }
     }
 
@@ -154,6 +169,7 @@ impl Search {
             facets: None,
             limit: Self::default_limit(),
             sort_by: None,
+            sort_order: SortOrder::default(),
         }
     }
 
@@ -171,6 +187,7 @@ pub struct SearchBuilder {
     facets: Option<FacetQuery>,
     limit: usize,
     sort_by: Option<String>,
+    sort_order: SortOrder,
 }
 
 impl Default for SearchBuilder {
@@ -186,6 +203,7 @@ impl SearchBuilder {
             facets: None,
             limit: Search::default_limit(),
             sort_by: None,
+            sort_order: SortOrder::default(),
         }
     }
 
@@ -208,8 +226,14 @@ impl SearchBuilder {
         self.sort_by = Some(field.to_string());
         self
     }
+    pub fn sort_order(mut self, order: SortOrder) -> Self {
+        self.sort_order = order;
+        self
+    }
     pub fn build(self) -> Search {
-        Search::new(Some(self.query), self.facets, self.limit, self.sort_by)
+        let mut search = Search::new(Some(self.query), self.facets, self.limit, self.sort_by);
+        search.sort_order = self.sort_order;
+        search
     }
 }
 
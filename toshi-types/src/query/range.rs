@@ -1,10 +1,14 @@
 use std::ops::Bound;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{to_value, Value};
 use tantivy::query::{Query as TantivyQuery, RangeQuery as TantivyRangeQuery};
-use tantivy::schema::{FieldType, Schema};
+use tantivy::schema::{Field, FieldType, Schema, Type};
+use tantivy::{DateTime, Term};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 
 use crate::query::{CreateQuery, KeyValue, Query};
 use crate::{error::Error, Result};
@@ -129,27 +133,89 @@ where
 }
 
 #[inline]
-fn include_exclude<V>(r: Option<Value>, r2: Option<Value>) -> Result<Bound<V>>
-where
-    V: DeserializeOwned,
-{
+fn include_exclude<V>(r: Option<Value>, r2: Option<Value>, parse: fn(Value) -> Result<V>) -> Result<Bound<V>> {
     if let Some(b) = r {
-        let value = serde_json::from_value(b).map_err(Error::from)?;
-        Ok(Bound::Excluded(value))
+        Ok(Bound::Excluded(parse(b)?))
     } else if let Some(b) = r2 {
-        let value = serde_json::from_value(b).map_err(Error::from)?;
-        Ok(Bound::Included(value))
+        Ok(Bound::Included(parse(b)?))
     } else {
         Ok(Bound::Unbounded)
     }
 }
 
 #[inline]
-fn create_ranges<V>(gte: Option<Value>, lte: Option<Value>, lt: Option<Value>, gt: Option<Value>) -> Result<(Bound<V>, Bound<V>)>
+fn from_json<V>(value: Value) -> Result<V>
 where
     V: DeserializeOwned,
 {
-    Ok((include_exclude(lt, lte)?, include_exclude(gt, gte)?))
+    serde_json::from_value(value).map_err(Error::from)
+}
+
+#[inline]
+fn create_ranges<V>(
+    gte: Option<Value>,
+    lte: Option<Value>,
+    lt: Option<Value>,
+    gt: Option<Value>,
+    parse: fn(Value) -> Result<V>,
+) -> Result<(Bound<V>, Bound<V>)> {
+    Ok((include_exclude(lt, lte, parse)?, include_exclude(gt, gte, parse)?))
+}
+
+/// Dates are given either as seconds since the unix epoch, as an RFC 3339 string like the ones date fields are
+/// returned in, or as `now` optionally followed by an offset like `now-30d`, the supported units are s, m, h, d and w
+fn parse_date(value: Value) -> Result<DateTime> {
+    let bad_date = |v: &Value| Error::QueryError(format!("Invalid date: {} for range query", v));
+    match &value {
+        Value::Number(n) => n.as_i64().and_then(timestamp).ok_or_else(|| bad_date(&value)),
+        Value::String(s) => date_math(s)
+            .and_then(timestamp)
+            .or_else(|| OffsetDateTime::parse(s, &Rfc3339).ok().map(DateTime::from_utc))
+            .ok_or_else(|| bad_date(&value)),
+        _ => Err(bad_date(&value)),
+    }
+}
+
+fn date_term_bound(field: Field, bound: Bound<DateTime>) -> Bound<Term> {
+    match bound {
+        Bound::Included(date) => Bound::Included(Term::from_field_date(field, date)),
+        Bound::Excluded(date) => Bound::Excluded(Term::from_field_date(field, date)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// The date some number of seconds from the unix epoch, or `None` when that's too far off to be a date
+fn timestamp(secs: i64) -> Option<DateTime> {
+    secs.checked_mul(1_000_000).map(DateTime::from_timestamp_micros)
+}
+
+/// Seconds since the unix epoch of a `now` expression, or `None` when it isn't one or its offset is out of range
+fn date_math(expr: &str) -> Option<i64> {
+    let offset = expr.trim().strip_prefix("now")?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    if offset.is_empty() {
+        return Some(now);
+    }
+    let (sign, amount) = if let Some(rest) = offset.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        (1, offset.strip_prefix('+')?)
+    };
+    let unit = match amount.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 60 * 60 * 24,
+        'w' => 60 * 60 * 24 * 7,
+        _ => return None,
+    };
+    let count = &amount[..amount.len() - 1];
+    // Only digits, parsing would otherwise take another sign in front of them
+    if count.is_empty() || !count.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let offset = count.parse::<i64>().ok()?.checked_mul(unit)?;
+    now.checked_add(sign * offset)
 }
 
 fn create_range_query(schema: &Schema, field: &str, r: Ranges) -> Result<Box<dyn TantivyQuery>> {
@@ -161,13 +227,18 @@ fn create_range_query(schema: &Schema, field: &str, r: Ranges) -> Result<Box<dyn
             let field_type = schema.get_field_entry(field).field_type();
             match field_type {
                 &FieldType::I64(_) => {
-                    let (upper, lower) = create_ranges::<i64>(gte, lte, lt, gt)?;
+                    let (upper, lower) = create_ranges::<i64>(gte, lte, lt, gt, from_json)?;
                     Ok(Box::new(TantivyRangeQuery::new_i64_bounds(field, lower, upper)))
                 }
                 &FieldType::U64(_) => {
-                    let (upper, lower) = create_ranges::<u64>(gte, lte, lt, gt)?;
+                    let (upper, lower) = create_ranges::<u64>(gte, lte, lt, gt, from_json)?;
                     Ok(Box::new(TantivyRangeQuery::new_u64_bounds(field, lower, upper)))
                 }
+                &FieldType::Date(_) => {
+                    let (upper, lower) = create_ranges(gte, lte, lt, gt, parse_date)?;
+                    let (lower, upper) = (date_term_bound(field, lower), date_term_bound(field, upper));
+                    Ok(Box::new(TantivyRangeQuery::new_term_bounds(field, Type::Date, &lower, &upper)))
+                }
                 ref ft => Err(Error::QueryError(format!("Invalid field type: {:?} for range query", ft))),
             }
         }
@@ -228,6 +299,53 @@ mod tests {
         assert!(!req.is_err());
     }
 
+    #[test]
+    fn test_date_math() {
+        let now = date_math("now").unwrap();
+        assert_eq!(date_math("now-30d").unwrap(), now - 30 * 24 * 60 * 60);
+        assert_eq!(date_math("now+2h").unwrap(), now + 2 * 60 * 60);
+        assert!(date_math("now-30y").is_none());
+        assert!(date_math("yesterday").is_none());
+        assert!(date_math("now-9999999999999999d").is_none());
+        assert!(date_math("now--5d").is_none());
+        assert!(date_math("now-+5d").is_none());
+        assert!(date_math("now-d").is_none());
+        assert!(parse_date(serde_json::json!(i64::MAX)).is_err());
+
+        let body = r#"{ "range" : { "timestamp" : { "lt" : "now-30d" } } }"#;
+        let mut schema = SchemaBuilder::new();
+        schema.add_date_field("timestamp", INDEXED);
+        let built = schema.build();
+        let req = serde_json::from_str::<RangeQuery>(body).unwrap().create_query(&built);
+        assert!(req.is_ok());
+    }
+
+    #[test]
+    fn test_date_range_search() -> Result<()> {
+        let mut builder = SchemaBuilder::new();
+        let timestamp = builder.add_date_field("timestamp", INDEXED | STORED);
+        let index = tantivy::Index::create_in_ram(builder.build());
+        let mut writer = index.writer(15_000_000)?;
+        for secs in [1_600_000_000, 1_600_000_100, 1_600_000_200] {
+            writer.add_document(tantivy::doc!(timestamp => DateTime::from_timestamp_secs(secs)))?;
+        }
+        writer.commit()?;
+        let searcher = index.reader()?.searcher();
+
+        let count = |body: &str| -> Result<usize> {
+            let query = serde_json::from_str::<RangeQuery>(body)?.create_query(&index.schema())?;
+            Ok(searcher.search(&query, &tantivy::collector::Count)?)
+        };
+        assert_eq!(count(r#"{ "range" : { "timestamp" : { "gte" : 1600000100 } } }"#)?, 2);
+        assert_eq!(
+            count(r#"{ "range" : { "timestamp" : { "gt" : "2020-09-13T12:26:40Z", "lte" : "2020-09-13T12:28:20Z" } } }"#)?,
+            1
+        );
+        assert_eq!(count(r#"{ "range" : { "timestamp" : { "lt" : "now" } } }"#)?, 3);
+        assert!(count(r#"{ "range" : { "timestamp" : { "lt" : "13/09/2020" } } }"#).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_range_builder() {
         let builder = RangeQuery::builder().gte(5).lte(10).for_field("test");
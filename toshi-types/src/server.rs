@@ -1,15 +1,23 @@
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use std::fmt::Formatter;
 use tantivy::schema::Schema;
 
+use crate::Query;
+
 /// In a delete query, this is returned indicating the number of documents that were removed
 /// by the delete.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DocsAffected {
-    /// The number of documents removed by the query
+    /// The number of committed documents that matched the delete when it was made
     pub docs_affected: u64,
+    /// Whether the delete still waits on a commit before searches stop seeing these documents
+    #[serde(default)]
+    pub pending: bool,
 }
 
 /// Indicates whether or not a commit should be done at the end of a document insert, the default
@@ -37,6 +45,253 @@ impl<D> AddDocument<D> {
     }
 }
 
+/// What a bulk insert does when a line can't be parsed
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OnError {
+    /// Leave the line out and carry on with the rest of the request. When the body can't be read the request fails,
+    /// but the documents written before then are kept.
+    #[default]
+    Skip,
+    /// Roll back the insert and fail the request. Writes made before the insert are committed when it starts, and the
+    /// index takes no other writes until it ends, so it can't start while anything else is writing to the index.
+    Rollback,
+}
+
+/// The format of the body of a bulk insert
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkFormat {
+    /// One JSON document per line
+    #[default]
+    Json,
+    /// Comma separated values
+    Csv,
+    /// Tab separated values
+    Tsv,
+}
+
+/// Options for a bulk insert given in its query string
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BulkOptions {
+    /// What to do with lines that can't be parsed
+    #[serde(default)]
+    pub on_error: OnError,
+    /// The format of the body
+    #[serde(default)]
+    pub format: BulkFormat,
+    /// The character separating columns of csv and tsv records, defaults to a comma or a tab
+    #[serde(default)]
+    pub delimiter: Option<char>,
+    /// The character columns containing delimiters or newlines are wrapped in, doubled to escape it
+    #[serde(default = "BulkOptions::default_quote")]
+    pub quote: char,
+    /// Whether the first record holds the field names of the columns
+    #[serde(default = "BulkOptions::default_header")]
+    pub header: bool,
+    /// Comma separated field names of the columns when the body has no header record
+    #[serde(default)]
+    pub columns: Option<String>,
+    /// The ingest pipeline to run each document through before it's indexed
+    #[serde(default)]
+    pub pipeline: Option<String>,
+    /// Whether to hold the request open until the insert is done, when false a task id is returned right away
+    #[serde(default = "BulkOptions::default_wait")]
+    pub wait: bool,
+}
+
+/// Options for document writes given in their query string
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WriteOptions {
+    /// The ingest pipeline to run documents through before they are indexed
+    #[serde(default)]
+    pub pipeline: Option<String>,
+}
+
+impl Default for BulkOptions {
+    fn default() -> Self {
+        Self {
+            on_error: OnError::default(),
+            format: BulkFormat::default(),
+            delimiter: None,
+            quote: Self::default_quote(),
+            header: Self::default_header(),
+            columns: None,
+            pipeline: None,
+            wait: Self::default_wait(),
+        }
+    }
+}
+
+impl BulkOptions {
+    /// The default quote character for csv and tsv records
+    pub const fn default_quote() -> char {
+        '"'
+    }
+
+    /// Csv and tsv bodies are expected to start with a header by default
+    pub const fn default_header() -> bool {
+        true
+    }
+
+    /// Bulk inserts wait for the insert to finish by default
+    pub const fn default_wait() -> bool {
+        true
+    }
+
+    /// The delimiter to split records with, `None` for json bodies
+    pub fn delimiter(&self) -> Option<char> {
+        match self.format {
+            BulkFormat::Json => None,
+            BulkFormat::Csv => Some(self.delimiter.unwrap_or(',')),
+            BulkFormat::Tsv => Some(self.delimiter.unwrap_or('\t')),
+        }
+    }
+}
+
+/// The index and document an action in a mixed bulk request applies to
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BulkTarget {
+    /// The index, or alias of a single index, the action applies to
+    #[serde(rename = "_index")]
+    pub index: String,
+    /// The primary key of the document the action applies to
+    #[serde(rename = "_id", default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// For deletes without an id, the term pairs documents are deleted by
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub terms: HashMap<String, String>,
+}
+
+/// The action line of a mixed bulk request, every action other than delete is followed by a line
+/// holding its document
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    /// Add a document
+    Index(BulkTarget),
+    /// Add or replace the document with the given id
+    Upsert(BulkTarget),
+    /// Merge a JSON patch into the stored fields of the document with the given id, or into the document
+    /// written with that id earlier in the same request
+    Update(BulkTarget),
+    /// Delete the document with the given id, or the documents matching the given terms
+    Delete(BulkTarget),
+}
+
+impl BulkAction {
+    /// The name of the action as it's written in a request
+    pub fn name(&self) -> &'static str {
+        match self {
+            BulkAction::Index(_) => "index",
+            BulkAction::Upsert(_) => "upsert",
+            BulkAction::Update(_) => "update",
+            BulkAction::Delete(_) => "delete",
+        }
+    }
+
+    /// What the action applies to
+    pub fn target(&self) -> &BulkTarget {
+        match self {
+            BulkAction::Index(t) | BulkAction::Upsert(t) | BulkAction::Update(t) | BulkAction::Delete(t) => t,
+        }
+    }
+
+    /// Whether the action is followed by a document line
+    pub fn has_source(&self) -> bool {
+        !matches!(self, BulkAction::Delete(_))
+    }
+}
+
+/// The request body for counting documents, when no query is given all documents are counted
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CountQuery {
+    /// The query documents have to match to be counted
+    #[serde(default)]
+    pub query: Option<Query>,
+}
+
+/// The request body for the _suggest route, for each field the text is split into terms using the
+/// field's tokenizer and every term is checked against the terms the field has indexed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SuggestRequest {
+    /// The text to find corrections for, keyed by the field to take suggestions from
+    pub fields: HashMap<String, String>,
+    /// The maximum edit distance between a term and a suggestion, at most 2
+    #[serde(default = "SuggestRequest::default_distance")]
+    pub distance: u8,
+    /// Whether swapping two adjacent characters counts as a single edit
+    #[serde(default = "SuggestRequest::default_transposition")]
+    pub transposition: bool,
+    /// The max number of suggestions to return per term
+    #[serde(default = "SuggestRequest::default_size")]
+    pub size: usize,
+}
+
+impl SuggestRequest {
+    /// Construct a suggest request with the default distance, transposition and size
+    pub fn new(fields: HashMap<String, String>) -> Self {
+        Self {
+            fields,
+            distance: Self::default_distance(),
+            transposition: Self::default_transposition(),
+            size: Self::default_size(),
+        }
+    }
+
+    /// The default maximum edit distance
+    pub const fn default_distance() -> u8 {
+        2
+    }
+
+    /// Transpositions are considered a single edit by default
+    pub const fn default_transposition() -> bool {
+        true
+    }
+
+    /// The default number of suggestions per term
+    pub const fn default_size() -> usize {
+        5
+    }
+}
+
+/// The query parameters of the _complete route
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CompleteRequest {
+    /// The field to take completions from
+    pub field: String,
+    /// The text completions have to start with, which has to hold at least one term
+    #[serde(default)]
+    pub prefix: String,
+    /// The max number of completions to return
+    #[serde(default = "CompleteRequest::default_size")]
+    pub size: usize,
+}
+
+impl CompleteRequest {
+    /// Construct a completion request for a field and prefix
+    pub fn new<F: ToString, P: ToString>(field: F, prefix: P, size: usize) -> Self {
+        Self {
+            field: field.to_string(),
+            prefix: prefix.to_string(),
+            size,
+        }
+    }
+
+    /// The default number of completions to return
+    pub const fn default_size() -> usize {
+        10
+    }
+}
+
+/// The header line of a search in a _msearch request, it names the index (or comma separated indexes
+/// and wildcard patterns) the search on the following line runs against
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MultiSearchHeader {
+    /// The index to search
+    pub index: String,
+}
+
 /// A wrapper around Tantivy's schema for when an index is created. [`tantivy::schema::Schema`]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SchemaBody(pub Schema);
@@ -52,6 +307,165 @@ impl std::fmt::Debug for SchemaBody {
     }
 }
 
+/// Toshi specific configuration of an index that is given when the index is created and is kept
+/// alongside the index's data
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct IndexConfig {
+    /// The field that uniquely identifies a document, when set, writing a document with the same
+    /// value in this field replaces the existing document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_key: Option<String>,
+    /// When the index's pending writes are committed by the commit watcher
+    #[serde(default, skip_serializing_if = "CommitPolicy::is_default")]
+    pub commit_policy: CommitPolicy,
+}
+
+/// When the commit watcher commits an index's pending writes, which is as soon as any of the limits is reached.
+/// Nothing is committed while a bulk insert into the index is running.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct CommitPolicy {
+    /// Seconds from one commit to the next, 0 turns off timed commits. Defaults to the server's
+    /// `auto_commit_duration`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<f32>,
+    /// Commit once this many documents are pending
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pending_docs: Option<u64>,
+    /// Commit once the documents pending add up to this many bytes, counted as they were sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pending_bytes: Option<u64>,
+    /// Up to this many seconds added to each interval so indexes with the same interval don't all commit at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<f32>,
+}
+
+impl CommitPolicy {
+    /// The longest interval or jitter a policy can have, a year in seconds
+    pub const MAX_SECONDS: f32 = 365.0 * 24.0 * 60.0 * 60.0;
+
+    /// Whether nothing in the policy has been set
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check the interval and jitter are usable numbers of seconds, no longer than [`Self::MAX_SECONDS`]
+    pub fn validate(&self) -> crate::Result<()> {
+        for (name, seconds) in [("interval", self.interval), ("jitter", self.jitter)] {
+            if let Some(seconds) = seconds.filter(|s| !(0.0..=Self::MAX_SECONDS).contains(s)) {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "Commit {} of {} is not a number of seconds up to {}",
+                    name,
+                    seconds,
+                    Self::MAX_SECONDS
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in the interval when the policy leaves it to the server's default
+    pub fn with_default_interval(mut self, default_interval: f32) -> Self {
+        self.interval.get_or_insert(default_interval);
+        self
+    }
+
+    /// Whether the pending writes have reached either limit
+    pub fn over_limit(&self, pending_docs: u64, pending_bytes: u64) -> bool {
+        self.max_pending_docs.is_some_and(|max| pending_docs >= max) || self.max_pending_bytes.is_some_and(|max| pending_bytes >= max)
+    }
+
+    /// How long to wait between commits, if timed commits are on and the wait is short enough to be a [`Duration`].
+    /// The jitter added is picked from `key`, so it only changes when the key does, such as an index's name along
+    /// with the time it was last committed
+    pub fn wait<K: Hash>(&self, default_interval: f32, key: K) -> Option<Duration> {
+        let interval = self.interval.unwrap_or(default_interval);
+        if interval <= 0.0 {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let fraction = (hasher.finish() % 1000) as f32 / 1000.0;
+        Duration::try_from_secs_f32(interval + self.jitter.unwrap_or(0.0) * fraction).ok()
+    }
+}
+
+/// The request body for creating an index, either just the schema or the schema along with the
+/// index's [`IndexConfig`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum CreateIndex {
+    /// Only a schema, the index gets the default config
+    Schema(SchemaBody),
+    /// A schema along with the config for the index
+    WithConfig {
+        /// The index's schema
+        schema: SchemaBody,
+        /// The index's config
+        #[serde(flatten)]
+        config: IndexConfig,
+    },
+}
+
+impl CreateIndex {
+    /// Split the body into the schema and config of the index to create
+    pub fn into_parts(self) -> (Schema, IndexConfig) {
+        match self {
+            CreateIndex::Schema(schema) => (schema.0, IndexConfig::default()),
+            CreateIndex::WithConfig { schema, config } => (schema.0, config),
+        }
+    }
+}
+
+/// A schema and config that indexes are created from when they are first written to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexTemplate {
+    /// Patterns of index names the template applies to, `*` stands in for any number of characters
+    pub index_patterns: Vec<String>,
+    /// The schema indexes are created with
+    pub schema: SchemaBody,
+    /// The config indexes are created with
+    #[serde(flatten)]
+    pub config: IndexConfig,
+    /// When several templates match an index name the one with the highest priority is used
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// An index an alias points at
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AliasTarget {
+    /// The index the alias points at
+    pub index: String,
+    /// The name of the alias
+    pub alias: String,
+}
+
+/// A change to make to the aliases of the catalog
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AliasAction {
+    /// Point an alias at an index, creating the alias if it does not exist
+    Add(AliasTarget),
+    /// Stop an alias pointing at an index, the alias is removed once it points at no indexes
+    Remove(AliasTarget),
+}
+
+/// The request body for changing aliases, all of the actions are applied together or none are
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateAliases {
+    /// The changes to make in order
+    pub actions: Vec<AliasAction>,
+}
+
+/// A single document looked up by its primary key
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetDocument<D> {
+    /// The primary key of the document
+    pub id: String,
+    /// The stored fields of the document
+    pub document: D,
+}
+
 /// The request body for performing a delete request to an index
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DeleteDoc {
@@ -62,10 +476,72 @@ pub struct DeleteDoc {
     pub terms: HashMap<String, String>,
 }
 
+/// The request body for rewriting fields on every document in an index that matches a query
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateByQuery {
+    /// Options surrounding the update [`IndexOptions`]
+    pub options: Option<IndexOptions>,
+    /// The query documents must match to be updated
+    pub query: Query,
+    /// The values to give fields on each matching document, a null removes the field
+    pub set: serde_json::Map<String, serde_json::Value>,
+    /// The number of documents reindexed each time the index writer is locked
+    #[serde(default = "UpdateByQuery::default_batch_size")]
+    pub batch_size: usize,
+}
+
+impl UpdateByQuery {
+    /// Create an update of the documents matching a query
+    pub fn new(query: Query, set: serde_json::Map<String, serde_json::Value>) -> Self {
+        Self {
+            options: None,
+            query,
+            set,
+            batch_size: Self::default_batch_size(),
+        }
+    }
+
+    /// The default number of documents reindexed per batch
+    pub const fn default_batch_size() -> usize {
+        1000
+    }
+}
+
+/// The request body for copying the stored documents of one index into another
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReindexRequest {
+    /// The index documents are read from
+    pub source: String,
+    /// The index documents are written to, it must already exist
+    pub dest: String,
+    /// Only documents matching this query are copied, when no query is given all documents are
+    #[serde(default)]
+    pub query: Option<Query>,
+    /// Source field names mapped to the destination field names they are renamed to
+    #[serde(default)]
+    pub fields: HashMap<String, String>,
+    /// Options surrounding the writes to the destination [`IndexOptions`]
+    pub options: Option<IndexOptions>,
+    /// The number of documents written each time the destination's writer is locked
+    #[serde(default = "UpdateByQuery::default_batch_size")]
+    pub batch_size: usize,
+}
+
+/// The request body for deleting every document in an index that matches a query
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeleteByQuery {
+    /// Options surrounding the delete [`IndexOptions`]
+    pub options: Option<IndexOptions>,
+    /// The query documents must match to be deleted
+    pub query: Query,
+}
+
 #[cfg(test)]
 mod tests {
 
-    use crate::SchemaBody;
+    use std::time::Duration;
+
+    use crate::{CommitPolicy, SchemaBody};
     use tantivy::schema::*;
 
     #[test]
@@ -81,4 +557,29 @@ mod tests {
 
         println!("{:?}", schema);
     }
+
+    #[test]
+    fn test_commit_policy() {
+        let policy: CommitPolicy = serde_json::from_str(r#"{"max_pending_docs": 10, "jitter": 2.0}"#).unwrap();
+        policy.validate().unwrap();
+        assert!(!policy.over_limit(9, 1 << 30));
+        assert!(policy.over_limit(10, 0));
+
+        let wait = policy.wait(5.0, ("index", 0)).unwrap();
+        assert!(wait >= Duration::from_secs(5) && wait <= Duration::from_secs(7));
+        assert_eq!(wait, policy.wait(5.0, ("index", 0)).unwrap());
+        assert_eq!(policy.wait(0.0, ("index", 0)), None);
+        assert_eq!(policy.with_default_interval(5.0).interval, Some(5.0));
+
+        let negative = CommitPolicy {
+            interval: Some(-1.0),
+            ..CommitPolicy::default()
+        };
+        assert!(negative.validate().is_err());
+
+        let too_long: CommitPolicy = serde_json::from_str(r#"{"interval": 1e30}"#).unwrap();
+        assert!(too_long.validate().is_err());
+        assert_eq!(too_long.wait(5.0, ("index", 0)), None);
+        assert_eq!(CommitPolicy::default().wait(f32::MAX, ("index", 0)), None);
+    }
 }
@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// The progress of a long running job, shared between the job doing the work and anyone reporting on it
+#[derive(Debug, Default)]
+pub struct TaskProgress {
+    total: AtomicU64,
+    read: AtomicU64,
+    written: AtomicU64,
+    errors: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+impl TaskProgress {
+    /// Set the number of documents the job expects to handle
+    pub fn set_total(&self, total: u64) {
+        self.total.store(total, Ordering::SeqCst);
+    }
+
+    /// Record documents read by the job
+    pub fn add_read(&self, read: u64) {
+        self.read.fetch_add(read, Ordering::SeqCst);
+    }
+
+    /// Record documents written by the job
+    pub fn add_written(&self, written: u64) {
+        self.written.fetch_add(written, Ordering::SeqCst);
+    }
+
+    /// Record documents the job failed to handle
+    pub fn add_errors(&self, errors: u64) {
+        self.errors.fetch_add(errors, Ordering::SeqCst);
+    }
+
+    /// Ask the job to stop at the next point it checks for cancellation
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the job has been asked to stop
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// A copy of the current counts in the form reported by the tasks API
+    pub fn status(&self, id: u64, action: &str, state: TaskState, error: Option<String>) -> TaskStatus {
+        TaskStatus {
+            id,
+            action: action.into(),
+            state,
+            total: self.total.load(Ordering::SeqCst),
+            read: self.read.load(Ordering::SeqCst),
+            written: self.written.load(Ordering::SeqCst),
+            errors: self.errors.load(Ordering::SeqCst),
+            error,
+        }
+    }
+}
+
+/// Where a task is in its lifetime
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    /// The task is still doing work
+    Running,
+    /// The task finished all of its work
+    Completed,
+    /// The task stopped because of an error
+    Failed,
+    /// The task stopped because it was cancelled
+    Cancelled,
+}
+
+/// The status of a task as returned by the _tasks route
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TaskStatus {
+    /// The id the task was started with
+    pub id: u64,
+    /// The kind of job the task is running
+    pub action: String,
+    /// Where the task is in its lifetime
+    pub state: TaskState,
+    /// The number of documents the task expects to handle, 0 when not known up front
+    pub total: u64,
+    /// Documents read so far
+    pub read: u64,
+    /// Documents written so far
+    pub written: u64,
+    /// Documents the task failed to handle
+    pub errors: u64,
+    /// The error a failed task stopped with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The response given when a request starts a background task
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct TaskCreated {
+    /// The id to look the task up by in the _tasks route
+    pub task: u64,
+}